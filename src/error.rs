@@ -1,3 +1,4 @@
+use crate::require;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 
@@ -5,10 +6,43 @@ use thiserror::Error;
 pub enum Error {
     #[error(transparent)]
     FromUtf8(#[from] FromUtf8Error),
-    #[error("only supports Version 1, but got {0}")]
+    #[error("only supports major version 0, but got version {}.{}", .0 >> 4, .0 & 0x0F)]
     UnsupportetVersion(u8),
     #[error("{0}")]
     MalformedData(String),
+    #[error("checksum wrong in page {page_sequence_number} of stream {bitstream_serial_number}")]
+    WrongChecksum {
+        bitstream_serial_number: u32,
+        page_sequence_number: u32,
+    },
+    #[error("comment {index} at byte {offset} with a declared length of {length} is invalid")]
+    MalformedComment {
+        index: u32,
+        /// offset of the length prefix in the comment packet
+        offset: usize,
+        length: u32,
+        #[source]
+        source: Box<Self>,
+    },
+    #[error(
+        "expected a packet starting with {expected:?}, but found one starting with {:?}",
+        String::from_utf8_lossy(found)
+    )]
+    UnexpectedPacket {
+        expected: &'static str,
+        /// the first bytes of the found packet
+        found: Vec<u8>,
+    },
+    #[error("expected an Opus stream, but found {0}")]
+    UnsupportedCodec(String),
+    #[error("reading {format} compressed data needs the {feature} feature")]
+    UnsupportedCompression {
+        format: &'static str,
+        /// the cargo feature, that enables decompressing `format`
+        feature: &'static str,
+    },
+    #[error("a value of {len} bytes is too long to be encoded")]
+    ValueTooLong { len: usize },
     #[error("reached an EoF while expecting more data")]
     UnexpectedEoF,
     #[error("reached an EoF after a finished packet")]
@@ -17,6 +51,62 @@ pub enum Error {
     Io(std::io::Error),
 }
 
+/// a coarse classification of [`Error`], see [`Error::kind`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorKind {
+    /// reading or writing failed, retrying may help
+    Io,
+    /// the data ended early, more data may help
+    Eof,
+    /// the data is malformed
+    Corrupt,
+    /// the data is valid, but not supported by this crate
+    Unsupported,
+}
+
+impl Error {
+    /// classifies `self`, see [`ErrorKind`]
+    pub const fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(_) => ErrorKind::Io,
+            Self::UnexpectedEoF | Self::NoMoreData => ErrorKind::Eof,
+            Self::FromUtf8(_)
+            | Self::MalformedData(_)
+            | Self::WrongChecksum { .. }
+            | Self::MalformedComment { .. }
+            | Self::UnexpectedPacket { .. } => ErrorKind::Corrupt,
+            Self::UnsupportetVersion(_)
+            | Self::UnsupportedCodec(_)
+            | Self::UnsupportedCompression { .. }
+            | Self::ValueTooLong { .. } => ErrorKind::Unsupported,
+        }
+    }
+    pub(crate) fn expect_starts_with(data: &[u8], expect: &[u8]) -> Result<(), Self> {
+        let data = &data[0..expect.len()];
+        require!(
+            expect == data,
+            Self::MalformedData(String::from_utf8(data.to_vec())
+            .and_then(|data| String::from_utf8(expect.to_vec()).map(|it| (it, data)))
+            .map_or_else(
+                |_| {
+                    format!("expected packet to start with MagicBytes {expect:?} but got {data:?}")
+                },
+                |(expect, data)| {
+                    format!("expected packet to start with MagicString {expect:?} but got {data:?}")
+                },
+            ),)
+        );
+        Ok(())
+    }
+    pub(crate) fn expect_starts_with_reader(
+        data: &mut impl std::io::Read,
+        expect: &[u8],
+    ) -> Result<(), Self> {
+        let mut buf = vec![0; expect.len()];
+        data.read_exact(&mut buf)?;
+        Self::expect_starts_with(&buf, expect)
+    }
+}
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         match err.kind() {
@@ -25,3 +115,70 @@ impl From<std::io::Error> for Error {
         }
     }
 }
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::UnexpectedEoF => Self::from(std::io::ErrorKind::UnexpectedEof),
+            err => Self::new(std::io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        let utf8_err = String::from_utf8(vec![0xFF]).unwrap_err();
+        for (kind, err) in [
+            (ErrorKind::Corrupt, Error::FromUtf8(utf8_err)),
+            (ErrorKind::Unsupported, Error::UnsupportetVersion(16)),
+            (ErrorKind::Corrupt, Error::MalformedData(String::new())),
+            (
+                ErrorKind::Corrupt,
+                Error::WrongChecksum {
+                    bitstream_serial_number: 0,
+                    page_sequence_number: 0,
+                },
+            ),
+            (
+                ErrorKind::Corrupt,
+                Error::MalformedComment {
+                    index: 0,
+                    offset: 0,
+                    length: 0,
+                    source: Box::new(Error::UnexpectedEoF),
+                },
+            ),
+            (
+                ErrorKind::Corrupt,
+                Error::UnexpectedPacket {
+                    expected: "OpusTags",
+                    found: Vec::new(),
+                },
+            ),
+            (
+                ErrorKind::Unsupported,
+                Error::UnsupportedCodec(String::new()),
+            ),
+            (
+                ErrorKind::Unsupported,
+                Error::UnsupportedCompression {
+                    format: "xz",
+                    feature: "xz",
+                },
+            ),
+            (ErrorKind::Unsupported, Error::ValueTooLong { len: 0 }),
+            (ErrorKind::Eof, Error::UnexpectedEoF),
+            (ErrorKind::Eof, Error::NoMoreData),
+            (
+                ErrorKind::Io,
+                Error::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied)),
+            ),
+        ] {
+            assert_eq!(kind, err.kind(), "wrong kind for {err:?}");
+        }
+    }
+}
@@ -1,37 +1,13 @@
 use common::require;
 
 pub mod error;
+pub mod ogg;
 pub mod opus_tagger;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
-mod ogg;
-
-impl error::Error {
-    pub(crate) fn expect_starts_with(data: &[u8], expect: &[u8]) -> Result<(), Self> {
-        let data = &data[0..expect.len()];
-        require!(
-            expect == data,
-            Self::MalformedData(String::from_utf8(data.to_vec())
-            .and_then(|data| String::from_utf8(expect.to_vec()).map(|it| (it, data)))
-            .map_or_else(
-                |_| {
-                    format!("expected packet to start with MagicBytes {expect:?} but got {data:?}")
-                },
-                |(expect, data)| {
-                    format!("expected packet to start with MagicString {expect:?} but got {data:?}")
-                },
-            ),)
-        );
-        Ok(())
-    }
-    pub(crate) fn expect_starts_with_reader(
-        data: &mut impl std::io::Read,
-        expect: &[u8],
-    ) -> Result<(), Self> {
-        let mut buf = vec![0; expect.len()];
-        data.read_exact(&mut buf)?;
-        Self::expect_starts_with(&buf, expect)
-    }
-}
+pub use ogg::MAGIC_STR as OGG_MAGIC;
+pub use opus_tagger::{HEAD_MAGIC_STR as OPUS_HEAD_MAGIC, TAGS_MAGIC_STR as OPUS_TAGS_MAGIC};
 
 struct MultiChain<Iter: Iterator> {
     iter: Iter,
@@ -69,12 +45,50 @@ where
     }
 }
 
+/// a [`std::io::Read`] that counts the bytes read from `inner`
+struct CountingRead<R> {
+    inner: R,
+    count: u64,
+}
+impl<R> CountingRead<R> {
+    const fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+impl<R: std::io::Read> std::io::Read for CountingRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// a [`std::io::Read`] that fails once with the contained error and is empty afterwards
+struct FailingRead(Option<std::io::Error>);
+impl FailingRead {
+    const fn new(err: std::io::Error) -> Self {
+        Self(Some(err))
+    }
+}
+impl std::io::Read for FailingRead {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.take().map_or(Ok(0), Err)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Read;
 
     use super::*;
 
+    #[test]
+    fn magic_strings() {
+        assert_eq!(b"OggS", OGG_MAGIC);
+        assert_eq!(b"OpusHead", OPUS_HEAD_MAGIC);
+        assert_eq!(b"OpusTags", OPUS_TAGS_MAGIC);
+    }
+
     #[test]
     fn multi_chain() {
         let raw_data = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
@@ -1,9 +1,11 @@
 #![allow(dead_code)]
 use itertools::Itertools;
 use std::{
+    collections::VecDeque,
     fmt::Debug,
-    io::{self, Read, Write},
+    io::{self, Read, Seek, Write},
     path::Path,
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -12,7 +14,13 @@ use crate::{
     require,
 };
 
-const MAGIC_STR: &[u8] = b"OggS";
+pub const MAGIC_STR: &[u8] = b"OggS";
+const ID3_MAGIC_STR: &[u8] = b"ID3";
+const ID3_FOOTER_FLAG: u8 = 0x10;
+/// the granule position of pages, on which no packet ends, see [`OggPage::has_granule`]
+pub const NO_GRANULE_POSITION: u64 = u64::MAX;
+/// the rate of the granule position of opus streams, regardless of the sample rate of the input
+pub const GRANULE_RATE: u64 = 48000;
 const OGG_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::Algorithm {
     width: 32,
     poly: 0x04C1_1DB7,
@@ -25,13 +33,13 @@ const OGG_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::Algorithm {
 });
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Clone)]
 pub struct OggPage {
     pub header_type: HeaderType,
     pub granule_position: u64,
     pub bitstream_serial_number: u32,
     pub page_sequence_number: u32,
-    /// invariant, len (and sublen) are bound to u8::MAX
+    /// invariant, len (and sublen) are bound to `u8::MAX`
     segment_table: Vec<Vec<u8>>,
 }
 
@@ -50,35 +58,95 @@ impl Debug for OggPage {
     }
 }
 
+/// a packet reassembled from the segments of one or more [`OggPage`]s
 #[derive(Debug, PartialEq, Eq)]
-pub enum HeaderType {
-    Simple,
-    Continuation,
-    BoS,
-    EoS,
+pub struct Packet {
+    /// granule position of the page, on which the packet was completed
+    pub granule_position: u64,
+    pub bitstream_serial_number: u32,
+    pub data: Vec<u8>,
+}
+
+/// the header of an [`OggPage`] without its payload, see [`OggPage::page_summaries`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PageSummary {
+    pub header_type: HeaderType,
+    pub granule_position: u64,
+    pub bitstream_serial_number: u32,
+    pub page_sequence_number: u32,
+    pub payload_len: usize,
 }
+impl PageSummary {
+    /// reads the header of the next page and skips its payload, without verifying the checksum
+    fn read_next_from(data: &mut impl Read) -> Result<Self, Error> {
+        let mut buf = [0; 27];
+        read_exact(data, &mut buf)?;
+        Error::expect_starts_with(&buf, MAGIC_STR)?;
+        OggPage::expect_version(&buf)?;
+        let mut segment_sizes = vec![0; buf[26] as usize];
+        data.read_exact(&mut segment_sizes)?;
+        let payload_len = segment_sizes.iter().map(|&it| it as usize).sum();
+        let skipped = io::copy(&mut data.take(payload_len as u64), &mut io::sink())?;
+        require!(skipped == payload_len as u64, Error::UnexpectedEoF);
 
+        Ok(Self {
+            header_type: buf[5]
+                .try_into()
+                .map_err(|err| Error::MalformedData(format!("unkown header_type {err}")))?,
+            granule_position: u64::from_le_bytes(buf[6..14].try_into().unwrap()),
+            bitstream_serial_number: u32::from_le_bytes(buf[14..18].try_into().unwrap()),
+            page_sequence_number: u32::from_le_bytes(buf[18..22].try_into().unwrap()),
+            payload_len,
+        })
+    }
+}
+
+/// the flags of an [`OggPage`], which can be combined with `|`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct HeaderType(u8);
+impl HeaderType {
+    pub const SIMPLE: Self = Self(0x00);
+    /// the first packet continues a packet of the previous page
+    pub const CONTINUATION: Self = Self(0x01);
+    /// begin of stream
+    pub const BOS: Self = Self(0x02);
+    /// end of stream
+    pub const EOS: Self = Self(0x04);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub const fn is_continuation(self) -> bool {
+        self.contains(Self::CONTINUATION)
+    }
+    pub const fn is_bos(self) -> bool {
+        self.contains(Self::BOS)
+    }
+    pub const fn is_eos(self) -> bool {
+        self.contains(Self::EOS)
+    }
+}
+impl std::ops::BitOr for HeaderType {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
 impl TryFrom<u8> for HeaderType {
     type Error = u8;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0x00 => Ok(Self::Simple),
-            0x01 => Ok(Self::Continuation),
-            0x02 => Ok(Self::BoS),
-            0x04 => Ok(Self::EoS),
-            value => Err(value),
+        if value & !0x07 == 0 {
+            Ok(Self(value))
+        } else {
+            Err(value)
         }
     }
 }
 impl From<HeaderType> for u8 {
     fn from(value: HeaderType) -> Self {
-        match value {
-            HeaderType::Simple => 0x00,
-            HeaderType::Continuation => 0x01,
-            HeaderType::BoS => 0x02,
-            HeaderType::EoS => 0x04,
-        }
+        value.0
     }
 }
 
@@ -90,7 +158,7 @@ pub enum SegmentToLarge {
     TooManySegments { size: usize },
 }
 impl SegmentToLarge {
-    fn validate(data: &Vec<Vec<u8>>) -> Result<(), Self> {
+    fn validate(data: &[Vec<u8>]) -> Result<(), Self> {
         require!(
             u8::try_from(data.len()).is_ok(),
             Self::TooManySegments { size: data.len() }
@@ -106,7 +174,7 @@ impl SegmentToLarge {
         }
         Ok(())
     }
-    fn validate_new(data: &Vec<Vec<u8>>, new: &Vec<u8>) -> Result<(), Self> {
+    fn validate_new(data: &[Vec<u8>], new: &[u8]) -> Result<(), Self> {
         require!(
             u8::try_from(data.len() + 1).is_ok(),
             Self::TooManySegments {
@@ -124,6 +192,8 @@ impl SegmentToLarge {
     }
 }
 impl OggPage {
+    /// # Errors
+    /// when `segment_table` violates the size limits of a page
     pub fn new(
         header_type: HeaderType,
         granule_position: u64,
@@ -143,23 +213,101 @@ impl OggPage {
     pub const fn segment_table(&self) -> &Vec<Vec<u8>> {
         &self.segment_table
     }
+    /// creates a page containing the single packet `payload`, see [`lace`]
+    ///
+    /// # Errors
+    /// when `payload` doesn't fit into a single page
+    pub fn from_payload(
+        header_type: HeaderType,
+        granule_position: u64,
+        bitstream_serial_number: u32,
+        page_sequence_number: u32,
+        payload: &[u8],
+    ) -> Result<Self, SegmentToLarge> {
+        Self::new(
+            header_type,
+            granule_position,
+            bitstream_serial_number,
+            page_sequence_number,
+            lace(payload),
+        )
+    }
+    pub const fn set_flags(&mut self, flags: HeaderType) {
+        self.header_type = flags;
+    }
+    pub const fn with_flags(mut self, flags: HeaderType) -> Self {
+        self.set_flags(flags);
+        self
+    }
+    /// `false` if no packet ends on this page, so its granule position is [`NO_GRANULE_POSITION`]
+    pub const fn has_granule(&self) -> bool {
+        self.granule_position != NO_GRANULE_POSITION
+    }
+    /// the playback time at the end of this page, based on the 48 kHz granule position of opus.
+    ///
+    /// returns `None` if no packet ends on this page, which is marked with a granule position of -1
+    pub fn granule_duration(&self, pre_skip: u16) -> Option<Duration> {
+        self.has_granule()
+            .then(|| samples_to_duration(self.granule_position.saturating_sub(pre_skip as u64)))
+    }
+    /// the granule position at the playback time `duration`, inverse of [`Self::granule_duration`]
+    pub const fn granule_from_duration(duration: Duration, pre_skip: u16) -> u64 {
+        (duration.as_nanos() * GRANULE_RATE as u128 / 1_000_000_000) as u64 + pre_skip as u64
+    }
+    /// compares the flags, granule position and segments, but neither serial nor sequence number
+    pub fn content_eq(&self, other: &Self) -> bool {
+        self.header_type == other.header_type
+            && self.granule_position == other.granule_position
+            && self.segment_table == other.segment_table
+    }
+    /// `true` if this is the last page of its logical stream
+    pub const fn is_eos(&self) -> bool {
+        self.header_type.is_eos()
+    }
+    /// the concatenated bytes of all segments
+    pub fn payload(&self) -> Vec<u8> {
+        self.segment_table.concat()
+    }
+    /// the length of the serialized page
+    pub fn byte_len(&self) -> usize {
+        27 + self.segment_table.len() + self.payload_len()
+    }
+    /// the length of [`Self::payload`] without allocating it
+    pub fn payload_len(&self) -> usize {
+        self.segment_table.iter().map(Vec::len).sum()
+    }
+    /// checks `segment_table` against the size limits of a page, like [`Self::new`] does
+    ///
+    /// # Errors
+    /// when there are more than 255 segments or a segment is longer than 255 bytes
+    pub fn validate_segments(segment_table: &[Vec<u8>]) -> Result<(), SegmentToLarge> {
+        SegmentToLarge::validate(segment_table)
+    }
+    /// # Errors
+    /// when `segment_table` violates the size limits of a page
     pub fn set_segment_table(&mut self, segment_table: Vec<Vec<u8>>) -> Result<(), SegmentToLarge> {
         SegmentToLarge::validate(&segment_table)?;
         self.segment_table = segment_table;
         Ok(())
     }
+    /// # Errors
+    /// when the page would violate its size limits with `segment`
     pub fn add_segment(&mut self, segment: Vec<u8>) -> Result<(), SegmentToLarge> {
         SegmentToLarge::validate_new(&self.segment_table, &segment)?;
         self.segment_table.push(segment);
         Ok(())
     }
 
+    /// # Errors
+    /// when writing to `writer` fails
     pub fn write_to(self, writer: &mut impl Write) -> Result<(), io::Error> {
+        debug_assert!(
+            !self.header_type.is_bos() || self.page_sequence_number == 0,
+            "only the first page of a stream may be flagged as begin of stream"
+        );
         let mut buf = Vec::new();
         // the exact size is known, so this is prefered over Vec::with_capacity
-        buf.reserve_exact(
-            27 + self.segment_table.len() + self.segment_table.iter().map(Vec::len).sum::<usize>(),
-        );
+        buf.reserve_exact(self.byte_len());
 
         buf.extend(MAGIC_STR);
         buf.push(0);
@@ -178,50 +326,109 @@ impl OggPage {
     }
 
     /// [spec](https://en.wikipedia.org/wiki/Ogg#Page_structure)
+    ///
+    /// # Errors
+    /// when `data` doesn't start with a valid page, or is already at its end
     pub fn read_next_from<R: Read>(data: &mut R) -> Result<Self, error::Error> {
-        let mut buf = vec![0; 27];
-        read_exact(data, &mut buf)?;
+        Self::read_next_from_with(data, true)
+    }
+    /// like [`Self::read_next_from`], but a wrong checksum is only reported when `verify_checksum` is set
+    ///
+    /// # Errors
+    /// see [`Self::read_next_from`]
+    pub fn read_next_from_with<R: Read>(
+        data: &mut R,
+        verify_checksum: bool,
+    ) -> Result<Self, error::Error> {
+        Self::read_next_into_with(data, verify_checksum, &mut Vec::new())
+    }
+    /// like [`Self::read_next_from`], but reuses `scratch` for the raw page instead of allocating a new buffer.
+    ///
+    /// the content of `scratch` is unspecified afterwards
+    ///
+    /// # Errors
+    /// see [`Self::read_next_from`]
+    pub fn read_next_into<R: Read>(
+        data: &mut R,
+        scratch: &mut Vec<u8>,
+    ) -> Result<Self, error::Error> {
+        Self::read_next_into_with(data, true, scratch)
+    }
+    fn read_next_into_with<R: Read>(
+        data: &mut R,
+        verify_checksum: bool,
+        buf: &mut Vec<u8>,
+    ) -> Result<Self, error::Error> {
+        let (page, has_valid_checksum) = Self::read_next_checked(data, buf)?;
+        require!(
+            has_valid_checksum || !verify_checksum,
+            error::Error::WrongChecksum {
+                bitstream_serial_number: page.bitstream_serial_number,
+                page_sequence_number: page.page_sequence_number
+            }
+        );
+        Ok(page)
+    }
+    /// reads the next page without rejecting a wrong checksum, but also returns whether the checksum was valid
+    pub(crate) fn read_next_checked<R: Read>(
+        data: &mut R,
+        buf: &mut Vec<u8>,
+    ) -> Result<(Self, bool), error::Error> {
+        buf.clear();
+        buf.resize(27, 0);
+        read_exact(data, buf)?;
 
-        error::Error::expect_starts_with(&buf, MAGIC_STR)?;
-        let page_segments = buf[26];
-        let mut segment_sizes = vec![0; page_segments as usize];
-        data.read_exact(&mut segment_sizes)?;
+        error::Error::expect_starts_with(buf, MAGIC_STR)?;
+        let page_segments = buf[26] as usize;
+        buf.resize(27 + page_segments, 0);
+        data.read_exact(&mut buf[27..])?;
+        let payload_len = buf[27..].iter().map(|&size| size as usize).sum::<usize>();
+        // read the whole page into one buffer to perform checksum
+        buf.resize(27 + page_segments + payload_len, 0);
+        data.read_exact(&mut buf[27 + page_segments..])?;
+
+        let (segment_sizes, mut payload) = buf[27..].split_at(page_segments);
         let segment_table = segment_sizes
             .iter()
-            .map(|size| {
-                let mut segment = vec![0; *size as usize];
-                data.read_exact(&mut segment).map(|()| segment)
+            .map(|&size| {
+                let (segment, rest) = payload.split_at(size as usize);
+                payload = rest;
+                segment.to_vec()
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect_vec();
 
-        // add all data that was read to one buffer to perform checksum
-        buf.extend(segment_sizes.iter().chain(segment_table.iter().flatten()));
-
-        require!(
-            Self::validate_checksum(&mut buf),
-            error::Error::MalformedData("checksum wrong".to_owned())
-        );
+        let bitstream_serial_number = u32::from_le_bytes(buf[14..18].try_into().unwrap());
+        let page_sequence_number = u32::from_le_bytes(buf[18..22].try_into().unwrap());
+        let has_valid_checksum = Self::validate_checksum(buf);
 
-        let version = buf[4];
-        assert_eq!(version, 0, "version is mandated to be zero");
-        Ok(Self {
+        Self::expect_version(buf)?;
+        let page = Self {
             header_type: buf[5]
                 .try_into()
                 .map_err(|err| error::Error::MalformedData(format!("unkown header_type {err}")))?,
             granule_position: u64::from_le_bytes(buf[6..14].try_into().unwrap()),
-            bitstream_serial_number: u32::from_le_bytes(buf[14..18].try_into().unwrap()),
-            page_sequence_number: u32::from_le_bytes(buf[18..22].try_into().unwrap()),
+            bitstream_serial_number,
+            page_sequence_number,
             segment_table,
-        })
+        };
+        Ok((page, has_valid_checksum))
     }
 
-    pub fn iterate_read(mut data: impl Read) -> impl Iterator<Item = Result<Self, error::Error>> {
+    pub fn iterate_read(data: impl Read) -> impl Iterator<Item = Result<Self, error::Error>> {
+        Self::iterate_read_with(data, true)
+    }
+    /// like [`Self::iterate_read`], but a wrong checksum is only reported when `verify_checksum` is set
+    pub fn iterate_read_with(
+        mut data: impl Read,
+        verify_checksum: bool,
+    ) -> impl Iterator<Item = Result<Self, error::Error>> {
         let mut is_finished = false;
+        let mut scratch = Vec::new();
         std::iter::from_fn(move || {
             if is_finished {
                 return None;
             }
-            match Self::read_next_from(&mut data) {
+            match Self::read_next_into_with(&mut data, verify_checksum, &mut scratch) {
                 Err(err) => {
                     is_finished = true; // prevent more data from being read
                     match err {
@@ -233,6 +440,97 @@ impl OggPage {
             }
         })
     }
+    /// like [`Self::iterate_read`], but only yields the pages of the logical stream with `serial`.
+    ///
+    /// errors are always yielded, as the serial number of a broken page is unknown
+    pub fn iterate_read_serial(
+        data: impl Read,
+        serial: u32,
+    ) -> impl Iterator<Item = Result<Self, error::Error>> {
+        Self::iterate_read(data).filter(move |page| {
+            page.as_ref()
+                .map_or(true, |page| page.bitstream_serial_number == serial)
+        })
+    }
+    /// counts the pages in `data`, see [`Self::page_summaries`]
+    ///
+    /// # Errors
+    /// see [`Self::page_summaries`]
+    pub fn count_pages(mut data: impl Read) -> Result<usize, error::Error> {
+        let mut count = 0;
+        loop {
+            match PageSummary::read_next_from(&mut data) {
+                Ok(_) => count += 1,
+                Err(Error::NoMoreData) => return Ok(count),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    /// reads the headers of all pages in `data`, skipping the payloads instead of keeping them.
+    ///
+    /// checksums aren't verified, as that would require reading the payloads
+    ///
+    /// # Errors
+    /// when reading fails or `data` contains a malformed or truncated page
+    pub fn page_summaries(mut data: impl Read) -> Result<Vec<PageSummary>, error::Error> {
+        let mut summaries = Vec::new();
+        loop {
+            match PageSummary::read_next_from(&mut data) {
+                Ok(summary) => summaries.push(summary),
+                Err(Error::NoMoreData) => return Ok(summaries),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    /// reassembles the packets of the pages in `data`, regardless of how they are distributed across pages
+    ///
+    /// a packet ends with the first segment, that is shorter than [`u8::MAX`]
+    pub fn iterate_packets(data: impl Read) -> impl Iterator<Item = Result<Packet, error::Error>> {
+        Self::iterate_packets_with(data, true)
+    }
+    /// like [`Self::iterate_packets`], but a wrong checksum is only reported when `verify_checksum` is set
+    pub fn iterate_packets_with(
+        data: impl Read,
+        verify_checksum: bool,
+    ) -> impl Iterator<Item = Result<Packet, error::Error>> {
+        Self::packets_of(Self::iterate_read_with(data, verify_checksum))
+    }
+    /// reassembles the packets contained in `pages`, see [`Self::iterate_packets`]
+    pub fn packets_of(
+        pages: impl IntoIterator<Item = Result<Self, error::Error>>,
+    ) -> impl Iterator<Item = Result<Packet, error::Error>> {
+        let mut pages = pages.into_iter();
+        let mut finished = VecDeque::new();
+        let mut partial: Option<Vec<u8>> = None;
+        std::iter::from_fn(move || loop {
+            if let Some(packet) = finished.pop_front() {
+                return Some(Ok(packet));
+            }
+            match pages.next() {
+                // the stream ended in the middle of a packet
+                None => return partial.take().map(|_| Err(Error::UnexpectedEoF)),
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok(page)) => {
+                    for segment in page.segment_table {
+                        let is_last = segment.len() < u8::MAX as usize;
+                        let mut data = partial.take().unwrap_or_default();
+                        data.extend(segment);
+                        if is_last {
+                            finished.push_back(Packet {
+                                granule_position: page.granule_position,
+                                bitstream_serial_number: page.bitstream_serial_number,
+                                data,
+                            });
+                        } else {
+                            partial = Some(data);
+                        }
+                    }
+                }
+            }
+        })
+    }
+    /// # Errors
+    /// when the file can't be opened
     #[allow(dead_code)]
     pub fn iterate_file(
         path: impl AsRef<Path>,
@@ -247,6 +545,24 @@ impl OggPage {
         check_bytes.swap_with_slice(&mut buf[22..26]);
         u32::from_le_bytes(check_bytes) == OGG_CRC.checksum(buf)
     }
+    /// rejects the page header `buf`, when its stream structure version isn't zero
+    fn expect_version(buf: &[u8]) -> Result<(), error::Error> {
+        let version = buf[4];
+        require!(
+            version == 0,
+            error::Error::MalformedData(format!(
+                "page {} of stream {} has version {version}, but it is mandated to be zero",
+                u32::from_le_bytes([buf[18], buf[19], buf[20], buf[21]]),
+                u32::from_le_bytes([buf[14], buf[15], buf[16], buf[17]]),
+            ))
+        );
+        Ok(())
+    }
+    /// recalculates the checksum of the serialized page `buf`, after its content was changed
+    pub(crate) fn update_checksum(buf: &mut [u8]) {
+        buf[22..26].fill(0);
+        Self::calculate_checksum(buf);
+    }
     /// # Panics
     /// expects checksum bytes (22..26) to be zero and will panic otherwise
     /// # Side effect
@@ -258,6 +574,191 @@ impl OggPage {
     }
 }
 
+/// writes the pages of a single logical stream, keeping their flags and sequence numbers consistent
+///
+/// the last pushed page is held back, until it is known whether it ends the stream
+pub struct StreamWriter<W> {
+    writer: W,
+    bitstream_serial_number: u32,
+    next_page_sequence_number: u32,
+    pending: Option<OggPage>,
+}
+impl<W: Write> StreamWriter<W> {
+    pub const fn new(writer: W, bitstream_serial_number: u32) -> Self {
+        Self {
+            writer,
+            bitstream_serial_number,
+            next_page_sequence_number: 0,
+            pending: None,
+        }
+    }
+    /// adds `page` to the stream, replacing its serial number, sequence number and flags
+    ///
+    /// # Errors
+    /// when writing the previous page fails
+    pub fn push(&mut self, mut page: OggPage) -> Result<(), io::Error> {
+        let mut header_type = HeaderType::SIMPLE;
+        if let Some(previous) = self.pending.take() {
+            // a packet continues on the next page, if the last segment is full
+            if previous
+                .segment_table
+                .last()
+                .is_some_and(|it| it.len() == u8::MAX as usize)
+            {
+                header_type = header_type | HeaderType::CONTINUATION;
+            }
+            previous.write_to(&mut self.writer)?;
+        }
+        if self.next_page_sequence_number == 0 {
+            header_type = header_type | HeaderType::BOS;
+        }
+        page.header_type = header_type;
+        page.bitstream_serial_number = self.bitstream_serial_number;
+        page.page_sequence_number = self.next_page_sequence_number;
+        self.next_page_sequence_number += 1;
+        self.pending = Some(page);
+        Ok(())
+    }
+    /// writes the last page flagged as end of stream and returns the inner writer
+    ///
+    /// # Errors
+    /// when writing the last page fails
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        if let Some(mut last) = self.pending.take() {
+            last.header_type = last.header_type | HeaderType::EOS;
+            last.write_to(&mut self.writer)?;
+        }
+        Ok(self.writer)
+    }
+}
+
+/// splits `packet` into segments of at most 255 bytes.
+///
+/// a packet with a length divisible by 255 is terminated with an empty segment
+pub fn lace(packet: &[u8]) -> Vec<Vec<u8>> {
+    let mut segments = packet
+        .chunks(u8::MAX as usize)
+        .map(<[u8]>::to_vec)
+        .collect_vec();
+    if packet.len().is_multiple_of(u8::MAX as usize) {
+        segments.push(Vec::new());
+    }
+    segments
+}
+
+/// converts a number of samples at [`GRANULE_RATE`] to the time they take to play
+pub(crate) fn samples_to_duration(samples: u64) -> Duration {
+    Duration::from_secs(samples / GRANULE_RATE)
+        + Duration::from_nanos((samples % GRANULE_RATE) * 1_000_000_000 / GRANULE_RATE)
+}
+
+/// returns the end of stream page of the stream with `serial` in `data`
+///
+/// only the end of `data` is scanned backwards for the last page of the stream,
+/// when the stream ends before that, all pages after the current position are read
+///
+/// # Errors
+/// when reading a page fails or `data` ends without such a page
+pub fn last_page(mut data: impl Read + Seek, serial: u32) -> Result<OggPage, Error> {
+    // holds at least one complete page of the maximal length
+    const TAIL_LEN: u64 = 2 * (27 + 255 + 255 * 255);
+    let start = data.stream_position()?;
+    let end = data.seek(io::SeekFrom::End(0))?;
+    let tail_start = end.saturating_sub(TAIL_LEN).max(start);
+    data.seek(io::SeekFrom::Start(tail_start))?;
+    let mut tail = Vec::new();
+    (&mut data).take(end - tail_start).read_to_end(&mut tail)?;
+    for i in (0..tail.len()).rev() {
+        if !tail[i..].starts_with(MAGIC_STR) {
+            continue;
+        }
+        // a false match of the magic string fails to read
+        let page = match OggPage::read_next_from(&mut &tail[i..]) {
+            Ok(page) => page,
+            Err(Error::MalformedData(_) | Error::WrongChecksum { .. } | Error::UnexpectedEoF) => {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+        if page.bitstream_serial_number == serial {
+            require!(
+                page.is_eos(),
+                Error::MalformedData(format!("no end of stream page for stream {serial}"))
+            );
+            return Ok(page);
+        }
+    }
+
+    data.seek(io::SeekFrom::Start(start))?;
+    OggPage::iterate_read(data)
+        .filter_ok(|page| page.bitstream_serial_number == serial && page.is_eos())
+        .next()
+        .unwrap_or_else(|| {
+            Err(Error::MalformedData(format!(
+                "no end of stream page for stream {serial}"
+            )))
+        })
+}
+
+/// skips an `ID3v2` tag at the current position of `data`, which some taggers prepend to ogg files against the spec
+///
+/// returns `true` if a tag was skipped, otherwise `data` is left at its position
+///
+/// # Errors
+/// when reading or seeking fails
+pub fn skip_id3(mut data: impl Read + Seek) -> Result<bool, Error> {
+    let start = data.stream_position()?;
+    let mut header = [0; 10];
+    let is_id3 = match data.read_exact(&mut header) {
+        // the size is a syncsafe integer, so the highest bit of each byte is unset
+        Ok(()) => header.starts_with(ID3_MAGIC_STR) && header[6..].iter().all(|it| it & 0x80 == 0),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(err) => return Err(err.into()),
+    };
+    if !is_id3 {
+        data.seek(io::SeekFrom::Start(start))?;
+        return Ok(false);
+    }
+    let size = header[6..]
+        .iter()
+        .fold(0, |size, &it| (size << 7) | i64::from(it));
+    let footer = if header[5] & ID3_FOOTER_FLAG == 0 {
+        0
+    } else {
+        10
+    };
+    data.seek(io::SeekFrom::Current(size + footer))?;
+    Ok(true)
+}
+/// moves `data` to the next [`MAGIC_STR`] after an optional `ID3v2` tag, see [`skip_id3`], and returns its offset
+///
+/// # Errors
+/// when reading or seeking fails or `data` contains no ogg page
+pub fn seek_to_ogg(mut data: impl Read + Seek) -> Result<u64, Error> {
+    skip_id3(&mut data)?;
+    let mut offset = data.stream_position()?;
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(i) = buf.windows(MAGIC_STR.len()).position(|it| it == MAGIC_STR) {
+            let position = offset + i as u64;
+            data.seek(io::SeekFrom::Start(position))?;
+            return Ok(position);
+        }
+        // keep the bytes, that may be the start of a magic string split across chunks
+        let drained = buf.len().saturating_sub(MAGIC_STR.len() - 1);
+        buf.drain(..drained);
+        offset += drained as u64;
+
+        let read = data.read(&mut chunk)?;
+        require!(
+            read > 0,
+            Error::MalformedData("no ogg page found".to_owned())
+        );
+        buf.extend(&chunk[..read]);
+    }
+}
+
 /// copy of [`Read::read_exact`], that reports [`Error::NoMoreData`] if nothing was read
 fn read_exact(read: &mut impl Read, mut buf: &mut [u8]) -> Result<(), Error> {
     let mut starts_at_eof = !buf.is_empty(); // will not detect EOF with zero_read
@@ -316,6 +817,349 @@ mod tests {
         }
     }
 
+    #[test]
+    fn payload_is_flattened_segments() {
+        let data_src = std::fs::File::open("./res/tag_test.opus").unwrap();
+        for page in OggPage::iterate_read(data_src).take(5) {
+            let page = page.unwrap();
+            let flattened = page.segment_table().iter().flatten().copied().collect_vec();
+            assert_eq!(flattened.len(), page.payload_len());
+            assert_eq!(flattened, page.payload());
+        }
+    }
+
+    #[test]
+    fn wrong_checksum_names_page() {
+        let mut data = std::fs::read("./res/tag_test.opus").unwrap();
+        let pages = OggPage::iterate_read(data.as_slice())
+            .take(3)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let third_start = pages[..2]
+            .iter()
+            .map(|it| 27 + it.segment_table().len() + it.payload_len())
+            .sum::<usize>();
+        // flip a byte in the payload of the third page
+        let last = third_start + 27 + pages[2].segment_table().len();
+        data[last] ^= 0xFF;
+
+        let err = OggPage::iterate_read(data.as_slice())
+            .find_map(Result::err)
+            .unwrap();
+        assert!(
+            matches!(
+                err,
+                Error::WrongChecksum {
+                    bitstream_serial_number,
+                    page_sequence_number: 2,
+                } if bitstream_serial_number == pages[2].bitstream_serial_number
+            ),
+            "expected a wrong checksum in page 2, got {err:?}"
+        );
+    }
+
+    #[test]
+    fn find_last_page() {
+        let mut data_src = std::fs::File::open("./res/tag_test.opus").unwrap();
+        let serial = OggPage::read_next_from(&mut data_src)
+            .unwrap()
+            .bitstream_serial_number;
+        data_src.rewind().unwrap();
+
+        let last = OggPage::iterate_read(&mut data_src)
+            .last()
+            .unwrap()
+            .unwrap();
+        assert!(last.is_eos(), "last page should be flagged EoS");
+        data_src.rewind().unwrap();
+        assert_eq!(last, last_page(&mut data_src, serial).unwrap());
+        data_src.rewind().unwrap();
+        assert!(last_page(&mut data_src, serial + 1).is_err());
+
+        // another stream fills the tail, so the first one is only found by reading all pages
+        let mut data = std::fs::read("./res/tag_test.opus").unwrap();
+        for seq in 0..3 {
+            let header_type = match seq {
+                0 => HeaderType::BOS,
+                2 => HeaderType::EOS,
+                _ => HeaderType::SIMPLE,
+            };
+            OggPage::from_payload(header_type, 0, serial + 1, seq, &vec![0; 255 * 255 - 1])
+                .unwrap()
+                .write_to(&mut data)
+                .unwrap();
+        }
+        let mut data = io::Cursor::new(data);
+        assert_eq!(last, last_page(&mut data, serial).unwrap());
+        data.rewind().unwrap();
+        let other = last_page(&mut data, serial + 1).unwrap();
+        assert_eq!(
+            (serial + 1, 2),
+            (other.bitstream_serial_number, other.page_sequence_number)
+        );
+    }
+
+    #[test]
+    fn read_into_scratch() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut allocating = data.as_slice();
+        let mut reusing = data.as_slice();
+        let mut scratch = Vec::new();
+        loop {
+            match OggPage::read_next_from(&mut allocating) {
+                Ok(page) => assert_eq!(
+                    page,
+                    OggPage::read_next_into(&mut reusing, &mut scratch).unwrap()
+                ),
+                Err(Error::NoMoreData) => break,
+                Err(err) => panic!("{err}"),
+            }
+        }
+        assert!(matches!(
+            OggPage::read_next_into(&mut reusing, &mut scratch),
+            Err(Error::NoMoreData)
+        ));
+    }
+
+    #[test]
+    fn summarize_pages() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let pages = OggPage::iterate_read(data.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(pages.len(), OggPage::count_pages(data.as_slice()).unwrap());
+
+        let summaries = OggPage::page_summaries(data.as_slice()).unwrap();
+        assert_eq!(pages.len(), summaries.len());
+        for (page, summary) in pages.iter().zip(&summaries) {
+            assert_eq!(
+                PageSummary {
+                    header_type: page.header_type,
+                    granule_position: page.granule_position,
+                    bitstream_serial_number: page.bitstream_serial_number,
+                    page_sequence_number: page.page_sequence_number,
+                    payload_len: page.payload_len(),
+                },
+                *summary
+            );
+        }
+
+        assert!(matches!(
+            OggPage::count_pages(&data[..data.len() - 1]),
+            Err(Error::UnexpectedEoF)
+        ));
+    }
+
+    #[test]
+    fn write_stream_flags() {
+        let mut writer = StreamWriter::new(Vec::new(), 7);
+        // the pushed flags and numbers are replaced
+        let first = OggPage::new(HeaderType::EOS, 0, 1, 5, vec![vec![1; 255]]).unwrap();
+        let second = OggPage::from_payload(HeaderType::BOS, 10, 1, 0, b"end").unwrap();
+        writer.push(first).unwrap();
+        writer.push(second).unwrap();
+        let buf = writer.finish().unwrap();
+
+        let pages = OggPage::iterate_read(buf.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(2, pages.len());
+        assert_eq!(HeaderType::BOS, pages[0].header_type);
+        assert_eq!(
+            HeaderType::CONTINUATION | HeaderType::EOS,
+            pages[1].header_type
+        );
+        for (i, page) in pages.iter().enumerate() {
+            assert_eq!(7, page.bitstream_serial_number);
+            assert_eq!(i as u32, page.page_sequence_number);
+        }
+
+        let mut writer = StreamWriter::new(Vec::new(), 7);
+        writer
+            .push(OggPage::from_payload(HeaderType::SIMPLE, 0, 7, 0, b"only").unwrap())
+            .unwrap();
+        let page = OggPage::read_next_from(&mut writer.finish().unwrap().as_slice()).unwrap();
+        assert_eq!(HeaderType::BOS | HeaderType::EOS, page.header_type);
+    }
+
+    #[test]
+    fn read_single_serial() {
+        let mut buf = Vec::new();
+        for (serial, payloads) in [(1, [&b"a"[..], b"b", b"c"]), (2, [b"x", b"y", b"z"])] {
+            let mut writer = StreamWriter::new(&mut buf, serial);
+            for payload in payloads {
+                writer
+                    .push(OggPage::from_payload(HeaderType::SIMPLE, 0, 0, 0, payload).unwrap())
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        for (serial, expected) in [(1, b"abc"), (2, b"xyz")] {
+            let pages = OggPage::iterate_read_serial(buf.as_slice(), serial)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert!(pages.iter().all(|it| it.bitstream_serial_number == serial));
+            assert_eq!(
+                expected.to_vec(),
+                pages.iter().flat_map(OggPage::payload).collect_vec()
+            );
+        }
+        assert_eq!(0, OggPage::iterate_read_serial(buf.as_slice(), 3).count());
+    }
+
+    #[test]
+    fn skip_prepended_id3() {
+        let opus = std::fs::read("./res/tag_test.opus").unwrap();
+        // a syncsafe size of 0x81 bytes, containing a fake magic string
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x01\x01".to_vec();
+        data.extend(b"OggS");
+        data.resize(10 + 0x81, 0);
+        data.extend(&opus);
+
+        let mut data = io::Cursor::new(data);
+        assert_eq!(10 + 0x81, seek_to_ogg(&mut data).unwrap());
+        assert_eq!(
+            OggPage::read_next_from(&mut opus.as_slice()).unwrap(),
+            OggPage::read_next_from(&mut data).unwrap()
+        );
+
+        let mut data = io::Cursor::new(opus.clone());
+        assert!(!skip_id3(&mut data).unwrap());
+        assert_eq!(0, seek_to_ogg(&mut data).unwrap());
+
+        let mut garbage = vec![b'O'; 5000];
+        garbage.extend(&opus);
+        assert_eq!(5000, seek_to_ogg(io::Cursor::new(garbage)).unwrap());
+        assert!(seek_to_ogg(io::Cursor::new(b"OggOgg")).is_err());
+    }
+    #[test]
+    fn combined_header_flags() {
+        let header_type = HeaderType::try_from(0x05).unwrap();
+        assert!(header_type.is_continuation());
+        assert!(header_type.is_eos());
+        assert!(!header_type.is_bos());
+        assert_eq!(HeaderType::CONTINUATION | HeaderType::EOS, header_type);
+        assert_eq!(Err(0x08), HeaderType::try_from(0x08));
+    }
+
+    #[test]
+    fn page_from_payload() {
+        let payload = (0..300).map(|i| i as u8).collect_vec();
+        let page = OggPage::from_payload(HeaderType::SIMPLE, 0, 1, 2, &payload).unwrap();
+        assert_eq!(
+            vec![255, 45],
+            page.segment_table().iter().map(Vec::len).collect_vec()
+        );
+        assert_eq!(payload, page.payload());
+
+        let page = OggPage::from_payload(HeaderType::SIMPLE, 0, 1, 2, &payload[..255]).unwrap();
+        assert_eq!(
+            vec![255, 0],
+            page.segment_table().iter().map(Vec::len).collect_vec()
+        );
+
+        assert!(OggPage::from_payload(HeaderType::SIMPLE, 0, 1, 2, &vec![0; 255 * 255]).is_err());
+    }
+
+    #[test]
+    fn validate_segment_table() {
+        OggPage::validate_segments(&vec![vec![0; 255]; 255]).unwrap();
+        OggPage::validate_segments(&[]).unwrap();
+        assert!(matches!(
+            OggPage::validate_segments(&vec![Vec::new(); 256]),
+            Err(SegmentToLarge::TooManySegments { size: 256 })
+        ));
+        assert!(matches!(
+            OggPage::validate_segments(&[vec![0; 3], vec![0; 256]]),
+            Err(SegmentToLarge::SegmentToLong {
+                size: 256,
+                position: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn reject_page_version() {
+        let mut buf = Vec::new();
+        OggPage::from_payload(HeaderType::BOS, 0, 3, 0, b"data")
+            .unwrap()
+            .write_to(&mut buf)
+            .unwrap();
+        buf[4] = 1;
+
+        let err = OggPage::read_next_from_with(&mut buf.as_slice(), false).unwrap_err();
+        assert!(matches!(err, Error::MalformedData(_)), "{err:?}");
+        assert!(OggPage::iterate_read(buf.as_slice())
+            .next()
+            .unwrap()
+            .is_err());
+        assert!(OggPage::count_pages(buf.as_slice()).is_err());
+        assert!(OggPage::page_summaries(buf.as_slice()).is_err());
+    }
+
+    #[test]
+    fn compare_content() {
+        let page = OggPage::from_payload(HeaderType::SIMPLE, 10, 1, 2, b"content").unwrap();
+        let mut renumbered = page.clone();
+        renumbered.page_sequence_number = 3;
+        renumbered.bitstream_serial_number = 4;
+        assert_ne!(page, renumbered);
+        assert!(page.content_eq(&renumbered));
+
+        renumbered.granule_position = 11;
+        assert!(!page.content_eq(&renumbered));
+        let other = OggPage::from_payload(HeaderType::SIMPLE, 10, 1, 2, b"other").unwrap();
+        assert!(!page.content_eq(&other));
+    }
+
+    #[test]
+    fn write_combined_flags() {
+        let page = OggPage::from_payload(HeaderType::SIMPLE, 0, 1, 3, b"continued")
+            .unwrap()
+            .with_flags(HeaderType::CONTINUATION | HeaderType::EOS);
+        let mut buf = Vec::new();
+        page.write_to(&mut buf).unwrap();
+        assert_eq!(0x05, buf[5]);
+
+        let read = OggPage::read_next_from(&mut buf.as_slice()).unwrap();
+        assert!(read.header_type.is_continuation());
+        assert!(read.is_eos());
+        assert!(!read.header_type.is_bos());
+    }
+    #[test]
+    #[should_panic(expected = "only the first page of a stream")]
+    #[cfg(debug_assertions)]
+    fn reject_late_bos() {
+        let mut page = OggPage::from_payload(HeaderType::SIMPLE, 0, 1, 3, b"late").unwrap();
+        page.set_flags(HeaderType::BOS);
+        page.write_to(&mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn granule_to_duration() {
+        let mut page = OggPage::from_payload(HeaderType::SIMPLE, 48_312, 1, 3, b"").unwrap();
+        assert_eq!(Some(Duration::from_secs(1)), page.granule_duration(312));
+        assert_eq!(
+            Some(Duration::from_millis(1006)),
+            page.granule_duration(0)
+                .map(|it| Duration::from_millis(it.as_millis() as u64))
+        );
+        assert_eq!(
+            page.granule_position,
+            OggPage::granule_from_duration(Duration::from_secs(1), 312)
+        );
+        assert_eq!(
+            24_000,
+            OggPage::granule_from_duration(Duration::from_millis(500), 0)
+        );
+
+        assert!(page.has_granule());
+        page.granule_position = NO_GRANULE_POSITION;
+        assert!(!page.has_granule());
+        assert_eq!(None, page.granule_duration(312));
+    }
+
     #[test]
     fn read_iter() {
         let data_src = std::fs::File::open(TEST_FILE).unwrap();
@@ -339,5 +1183,9 @@ mod tests {
             oggs.len(),
             "failed to read all 1987 packets in data"
         );
+        assert_eq!(
+            NUMBER_OGG_PACKETS,
+            OggPage::count_pages(std::fs::File::open(TEST_FILE).unwrap()).unwrap()
+        );
     }
 }
@@ -1,26 +1,53 @@
 use std::{
     fmt::Debug,
-    io::{Read, Write},
-    path::Path,
+    io::{Read, Seek, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
 use crate::{
     error::{self, Error},
-    ogg::OggPage,
-    require, MultiChain,
+    ogg::{
+        lace, last_page, samples_to_duration, HeaderType, OggPage, Packet, StreamWriter,
+        NO_GRANULE_POSITION,
+    },
+    require, CountingRead, FailingRead, MultiChain,
 };
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 
-const HEAD_MAGIC_STR: &[u8] = b"OpusHead";
-const HEAD_VERSION: u8 = 1;
-#[derive(Debug, PartialEq, Eq)]
+pub const HEAD_MAGIC_STR: &[u8] = b"OpusHead";
+/// magic strings of the first packet of other codecs, that can be contained in an ogg stream
+const OTHER_CODECS: [(&[u8], &str); 5] = [
+    (b"\x01vorbis", "Vorbis"),
+    (b"\x7fFLAC", "FLAC"),
+    (b"Speex   ", "Speex"),
+    (b"\x80theora", "Theora"),
+    (b"\x80kate\0\0\0", "Kate"),
+];
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct OpusHead {
+    /// granule position of the page containing the header, mandated to be zero by the spec, but tolerated otherwise
+    granule_position: u64,
     version: u8,
     channel_count: u8,
     pre_skip: u16,
-    sample_rate: SampleRate,
+    /// the sample rate of the original input in Hz, only informational, so any value is kept
+    sample_rate: u32,
     gain: Gain,
     channel_map: MappingFamily,
+    /// only present, when `channel_map` isn't [`MappingFamily::RTP`]
+    mapping_table: Option<ChannelMappingTable>,
+}
+/// [spec](https://wiki.xiph.org/OggOpus#Channel_Mapping)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ChannelMappingTable {
+    stream_count: u8,
+    coupled_count: u8,
+    /// one entry per channel
+    channel_mapping: Vec<u8>,
+    /// uninterpreted bytes after `channel_mapping`, only allowed for [`MappingFamily::NotDefined`]
+    extra: Vec<u8>,
 }
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum MappingFamily {
@@ -46,6 +73,28 @@ impl From<MappingFamily> for u8 {
         }
     }
 }
+/// the meaning of the channels of an [`OpusHead`]
+///
+/// [spec](https://www.rfc-editor.org/rfc/rfc7845#section-5.1.1)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    /// left, center, right
+    Linear,
+    Quadraphonic,
+    /// 5.0 surround
+    FiveZero,
+    /// 5.1 surround
+    FiveOne,
+    /// 6.1 surround
+    SixOne,
+    /// 7.1 surround
+    SevenOne,
+    /// mapping families 2 and 3
+    Ambisonics,
+    Unknown,
+}
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum SampleRate {
     KHz8,
@@ -54,15 +103,37 @@ pub enum SampleRate {
     KHz24,
     KHz48,
 }
+impl SampleRate {
+    pub const ALL: [Self; 5] = [
+        Self::KHz8,
+        Self::KHz12,
+        Self::KHz16,
+        Self::KHz24,
+        Self::KHz48,
+    ];
+
+    /// returns the matching rate, if `hz` is one of the standard rates
+    pub fn known(hz: u32) -> Option<Self> {
+        Self::try_from(hz).ok()
+    }
+    pub const fn as_hz(self) -> u32 {
+        match self {
+            Self::KHz8 => 8000,
+            Self::KHz12 => 12000,
+            Self::KHz16 => 16000,
+            Self::KHz24 => 24000,
+            Self::KHz48 => 48000,
+        }
+    }
+}
+impl std::fmt::Display for SampleRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_hz())
+    }
+}
 impl From<SampleRate> for u32 {
     fn from(value: SampleRate) -> Self {
-        match value {
-            SampleRate::KHz8 => 8000,
-            SampleRate::KHz12 => 12000,
-            SampleRate::KHz16 => 16000,
-            SampleRate::KHz24 => 24000,
-            SampleRate::KHz48 => 48000,
-        }
+        value.as_hz()
     }
 }
 impl From<SampleRate> for [u8; 4] {
@@ -89,73 +160,195 @@ pub struct Gain {
     m: i8, // only 7 bits for M, first bit is sign
     n: u8,
 }
+impl Gain {
+    /// the gain in decibel, stored as a little endian number in Q7.8 format
+    pub fn as_db(self) -> f64 {
+        f64::from(i16::from_le_bytes([self.m.to_le_bytes()[0], self.n])) / 256.0
+    }
+    /// the gain closest to `db`, clamped to the range of Q7.8
+    pub fn from_db(db: f64) -> Self {
+        // float to int casts saturate
+        let [m, n] = ((db * 256.0).round() as i16).to_le_bytes();
+        Self {
+            m: i8::from_le_bytes([m]),
+            n,
+        }
+    }
+}
 
 impl OpusHead {
-    #[allow(dead_code)]
-    fn to_bytes(&self) -> Vec<u8> {
+    /// the high nibble of the version, always 0 for supported streams
+    pub const fn major_version(&self) -> u8 {
+        self.version >> 4
+    }
+    /// the low nibble of the version, which doesn't affect compatibility
+    pub const fn minor_version(&self) -> u8 {
+        self.version & 0x0F
+    }
+    /// the sample rate of the original input in Hz, which may be any value, see [`SampleRate::known`]
+    pub const fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    /// the number of opus streams in each packet, as needed by a multistream decoder.
+    ///
+    /// `None` for [`MappingFamily::RTP`], which implies a single stream
+    pub fn stream_count(&self) -> Option<u8> {
+        self.mapping_table.as_ref().map(|it| it.stream_count)
+    }
+    /// the number of streams, that are coupled stereo streams, see [`Self::stream_count`]
+    pub fn coupled_count(&self) -> Option<u8> {
+        self.mapping_table.as_ref().map(|it| it.coupled_count)
+    }
+    /// the granule position of the page, this header was read from
+    pub const fn granule_position(&self) -> u64 {
+        self.granule_position
+    }
+    /// returns `true` if the page of this header had a granule position of zero, as mandated by the spec.
+    ///
+    /// Some muxers write a nonzero value, which is accepted when reading, so strict callers can reject it with this.
+    pub const fn has_zero_granule(&self) -> bool {
+        self.granule_position == 0
+    }
+    /// interprets `channel_count` according to `channel_map`
+    pub const fn layout(&self) -> ChannelLayout {
+        match (self.channel_map, self.channel_count) {
+            (MappingFamily::RTP | MappingFamily::VorbisChannelOrder, 1) => ChannelLayout::Mono,
+            (MappingFamily::RTP | MappingFamily::VorbisChannelOrder, 2) => ChannelLayout::Stereo,
+            (MappingFamily::VorbisChannelOrder, 3) => ChannelLayout::Linear,
+            (MappingFamily::VorbisChannelOrder, 4) => ChannelLayout::Quadraphonic,
+            (MappingFamily::VorbisChannelOrder, 5) => ChannelLayout::FiveZero,
+            (MappingFamily::VorbisChannelOrder, 6) => ChannelLayout::FiveOne,
+            (MappingFamily::VorbisChannelOrder, 7) => ChannelLayout::SixOne,
+            (MappingFamily::VorbisChannelOrder, 8) => ChannelLayout::SevenOne,
+            (MappingFamily::NotDefined(2 | 3), _) => ChannelLayout::Ambisonics,
+            _ => ChannelLayout::Unknown,
+        }
+    }
+    /// the permutation from the decoded channels to the WAVE channel order, which most PCM consumers expect.
+    ///
+    /// the `i`th output channel is the decoded channel at index `result[i]`.
+    /// returns `None`, if [`Self::layout`] doesn't know the channels
+    pub fn decode_channel_order(&self) -> Option<Vec<usize>> {
+        // the decoded channels are in vorbis order, e.g. front left, center, front right for `Linear`
+        let order: &[usize] = match self.layout() {
+            ChannelLayout::Mono => &[0],
+            ChannelLayout::Stereo => &[0, 1],
+            ChannelLayout::Linear => &[0, 2, 1],
+            ChannelLayout::Quadraphonic => &[0, 1, 2, 3],
+            ChannelLayout::FiveZero => &[0, 2, 1, 3, 4],
+            ChannelLayout::FiveOne => &[0, 2, 1, 5, 3, 4],
+            ChannelLayout::SixOne => &[0, 2, 1, 6, 5, 3, 4],
+            ChannelLayout::SevenOne => &[0, 2, 1, 7, 5, 6, 3, 4],
+            ChannelLayout::Ambisonics | ChannelLayout::Unknown => return None,
+        };
+        Some(order.to_vec())
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::new();
-        buf.reserve_exact(19);
+        buf.reserve_exact(
+            19 + self.mapping_table.as_ref().map_or(0, |table| {
+                2 + table.channel_mapping.len() + table.extra.len()
+            }),
+        );
         buf.extend(HEAD_MAGIC_STR);
-        buf.push(HEAD_VERSION);
+        buf.push(self.version);
         buf.push(self.channel_count);
         buf.extend(self.pre_skip.to_le_bytes());
-        buf.extend(<SampleRate as Into<[u8; 4]>>::into(self.sample_rate));
+        buf.extend(self.sample_rate.to_le_bytes());
         buf.push(self.gain.m.to_le_bytes()[0]);
         buf.push(self.gain.n);
         buf.push(self.channel_map.into());
 
-        // TODO Optional Channel Mapping
+        if let Some(table) = &self.mapping_table {
+            buf.push(table.stream_count);
+            buf.push(table.coupled_count);
+            buf.extend(&table.channel_mapping);
+            buf.extend(&table.extra);
+        }
         buf
     }
     /// [spec](https://wiki.xiph.org/OggOpus#ID_Header)
     fn from(ogg_head: &OggPage) -> Result<Self, error::Error> {
-        assert_eq!(ogg_head.granule_position, 0, "granule needs to be zero");
-        require!(
-            ogg_head.segment_table().len() == 1,
-            error::Error::MalformedData(format!(
-                "expected one segment, got mutliple with sizes: {:?}",
-                ogg_head.segment_table().iter().map(Vec::len).collect_vec()
-            ))
-        );
-        let buf = ogg_head.segment_table()[0].as_slice();
+        Self::from_packet(&ogg_head.payload(), ogg_head.granule_position)
+    }
+    /// parses the ID header packet `buf`, which was completed on a page with `granule_position`
+    fn from_packet(buf: &[u8], granule_position: u64) -> Result<Self, error::Error> {
+        if let Some((_, codec)) = OTHER_CODECS
+            .iter()
+            .find(|(magic, _)| buf.starts_with(magic))
+        {
+            return Err(error::Error::UnsupportedCodec((*codec).to_owned()));
+        }
+        let mut head = Self::from_bytes(buf)?;
+        head.granule_position = granule_position;
+        Ok(head)
+    }
+    /// parses the content of an ID header packet, independent of the containing [`OggPage`]
+    ///
+    /// [spec](https://wiki.xiph.org/OggOpus#ID_Header)
+    ///
+    /// # Errors
+    /// when `buf` isn't a valid ID header
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, error::Error> {
         require!(
-            buf.len() == 19, // maybe can be 19..19+(channel*8)
+            buf.len() >= 19,
             error::Error::MalformedData(format!(
-                "OpusHead needs to be length 19, but was {}",
+                "OpusHead needs to be at least length 19, but was {}",
                 buf.len(),
             ))
         );
 
         error::Error::expect_starts_with(buf, HEAD_MAGIC_STR)?;
 
+        // the high nibble is the major version, only its minor versions are compatible
         let version = buf[8];
-        require!(version <= 15, error::Error::UnsupportetVersion(version));
+        require!(version >> 4 == 0, error::Error::UnsupportetVersion(version));
         // TODO validate
         let channel_count = buf[9];
         let channel_map = buf[18].into();
 
+        let expected_len = match channel_map {
+            MappingFamily::RTP => 19,
+            _ => 21 + channel_count as usize,
+        };
+        // undefined families may extend the table, those bytes are kept without interpreting them
+        let allows_extra = matches!(channel_map, MappingFamily::NotDefined(_));
+        require!(
+            buf.len() == expected_len || (allows_extra && buf.len() > expected_len),
+            error::Error::MalformedData(format!(
+                "OpusHead with {channel_count} channels and {channel_map:?} needs to be length {expected_len}, but was {}",
+                buf.len(),
+            ))
+        );
+        let mapping_table = (channel_map != MappingFamily::RTP).then(|| ChannelMappingTable {
+            stream_count: buf[19],
+            coupled_count: buf[20],
+            channel_mapping: buf[21..expected_len].to_vec(),
+            extra: buf[expected_len..].to_vec(),
+        });
+
         Ok(Self {
+            granule_position: 0,
             version,
             channel_count,
-            pre_skip: u16::from_le_bytes(buf[10..12].try_into().unwrap()),
-            sample_rate: u32::from_le_bytes(buf[12..16].try_into().unwrap())
-                .try_into()
-                .unwrap(),
+            pre_skip: u16::from_le_bytes([buf[10], buf[11]]),
+            sample_rate: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
             gain: Gain {
                 m: i8::from_le_bytes([buf[16]]),
                 n: buf[17],
             },
             channel_map,
+            mapping_table,
         })
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct VorbisComment {
     vendor: String,
     comments: Vec<Comment>,
 }
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Comment {
     pub key: String,
     pub value: String,
@@ -168,6 +361,139 @@ impl<IntoK: Into<String>, IntoV: Into<String>> From<(IntoK, IntoV)> for Comment
         }
     }
 }
+impl Comment {
+    /// splits `s` at the first `=` into key and value, like `TITLE=Foo`.
+    ///
+    /// an empty value like in `COMMENT=` is valid, but the separator itself is required by the spec
+    ///
+    /// # Errors
+    /// when `s` contains no `=`, even a bare key like `COMMENT` isn't accepted as a tag without a value
+    pub fn parse(s: &str) -> Result<Self, error::Error> {
+        let (key, value) = s.splitn(2, '=').collect_tuple().ok_or_else(|| {
+            error::Error::MalformedData(format!(
+                "comment {s:?} has no '=' between key and value, an empty value needs to be written as \"{s}=\""
+            ))
+        })?;
+        Ok((key, value).into())
+    }
+}
+/// the library, that wrote the vendor string of a [`VorbisComment`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EncoderLibrary {
+    /// ffmpeg's container library, with vendor strings like `Lavf60.3.100`
+    Libavformat,
+    /// the reference encoder, used by opusenc, with vendor strings like `libopus 1.3.1`
+    Libopus,
+    /// the whole unrecognized vendor string
+    Other(String),
+}
+/// information about the tool, that produced a file, see [`VorbisComment::encoder_info`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EncoderInfo {
+    pub library: EncoderLibrary,
+    pub version: Option<String>,
+    /// the first `ENCODER` comment
+    pub encoder: Option<String>,
+    /// the first `ENCODED-BY` comment
+    pub encoded_by: Option<String>,
+}
+/// a chapter mark in the `CHAPTERxxx=HH:MM:SS.mmm` and `CHAPTERxxxNAME` comments, see [`VorbisComment::chapters`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Chapter {
+    pub start: Duration,
+    pub name: Option<String>,
+}
+/// the common metadata of an audiobook, mapped to the standard keys of a [`VorbisComment`]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AudiobookMeta {
+    /// `TITLE`
+    pub title: Option<String>,
+    /// `AUTHOR`
+    pub author: Option<String>,
+    /// `PERFORMER`, or `ARTIST` when there is no `PERFORMER`
+    pub narrator: Option<String>,
+    /// `ALBUM`
+    pub series: Option<String>,
+    /// the number of `TRACKNUMBER`, see [`VorbisComment::track`]
+    pub part: Option<u32>,
+    pub chapters: Vec<Chapter>,
+}
+impl From<&VorbisComment> for AudiobookMeta {
+    fn from(tags: &VorbisComment) -> Self {
+        let get = |key| tags.get_first(key).map(str::to_owned);
+        Self {
+            title: get("TITLE"),
+            author: get("AUTHOR"),
+            narrator: get("PERFORMER").or_else(|| get("ARTIST")),
+            series: get("ALBUM"),
+            part: tags.track().map(|(part, _)| part),
+            chapters: tags.chapters(),
+        }
+    }
+}
+impl AudiobookMeta {
+    /// writes every present field to `tags`, replacing the comments with the same key.
+    ///
+    /// missing fields leave `tags` untouched, the chapters are always replaced.
+    /// the narrator is written as `PERFORMER`, the total number of tracks is kept
+    pub fn write_to(&self, tags: &mut VorbisComment) {
+        for (key, value) in [
+            ("TITLE", &self.title),
+            ("AUTHOR", &self.author),
+            ("PERFORMER", &self.narrator),
+            ("ALBUM", &self.series),
+        ] {
+            if let Some(value) = value {
+                tags.set_single(key, value.clone());
+            }
+        }
+        if let Some(part) = self.part {
+            let total = tags.track().and_then(|(_, total)| total);
+            tags.set_track(part, total);
+        }
+        tags.set_chapters(&self.chapters);
+    }
+}
+/// the vendor string used by [`VorbisComment::default`]
+pub const DEFAULT_VENDOR: &str = concat!("opus_tag ", env!("CARGO_PKG_VERSION"));
+impl Default for VorbisComment {
+    fn default() -> Self {
+        Self::empty(DEFAULT_VENDOR)
+    }
+}
+impl<C: Into<Comment>> Extend<C> for VorbisComment {
+    fn extend<T: IntoIterator<Item = C>>(&mut self, iter: T) {
+        self.append_all(iter);
+    }
+}
+impl std::ops::Index<&str> for VorbisComment {
+    type Output = str;
+
+    /// returns the value of the first comment with `key`
+    ///
+    /// # Panics
+    /// when there is no comment with `key`, use [`VorbisComment::get_first`] to check instead
+    fn index(&self, key: &str) -> &Self::Output {
+        self.get_first(key)
+            .unwrap_or_else(|| panic!("no comment with key {key:?}"))
+    }
+}
+/// uppercase keys used by some taggers and the canonical key [`VorbisComment::canonicalize_keys`] replaces them with
+pub const KEY_ALIASES: &[(&str, &str)] = &[
+    ("ALBUM ARTIST", "ALBUMARTIST"),
+    ("ALBUM_ARTIST", "ALBUMARTIST"),
+    ("YEAR", "DATE"),
+    ("TOTALTRACKS", "TRACKTOTAL"),
+    ("TOTALDISCS", "DISCTOTAL"),
+    ("DISKNUMBER", "DISCNUMBER"),
+    ("DISKTOTAL", "DISCTOTAL"),
+];
+/// the case, that [`VorbisComment::normalize_keys`] converts to
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyCase {
+    Upper,
+    Lower,
+}
 impl VorbisComment {
     pub fn empty(vendor: impl Into<String>) -> Self {
         Self {
@@ -175,6 +501,10 @@ impl VorbisComment {
             comments: Vec::new(),
         }
     }
+    /// a comment header without any comments, that only keeps `vendor`, as written by [`Self::strip_tags_file`]
+    pub fn stripped(vendor: impl Into<String>) -> Self {
+        Self::empty(vendor)
+    }
     pub fn new<Iter: IntoIterator>(vendor: impl Into<String>, comments: Iter) -> Self
     where
         Iter::Item: Into<Comment>,
@@ -190,11 +520,61 @@ impl VorbisComment {
     pub fn add_comment(&mut self, comment: impl Into<Comment>) {
         self.comments.push(comment.into());
     }
+    /// adds all `comments` in order
+    pub fn append_all<Iter: IntoIterator>(&mut self, comments: Iter)
+    where
+        Iter::Item: Into<Comment>,
+    {
+        self.comments
+            .extend(comments.into_iter().map(Into::<Comment>::into));
+    }
+    /// adds a binary value, that is already base64 encoded like `METADATA_BLOCK_PICTURE`.
+    ///
+    /// the value is stored as it is, without decoding or validating it
+    pub fn add_raw(&mut self, key: impl Into<String>, base64_value: impl Into<String>) {
+        self.add_comment((key, base64_value));
+    }
+    /// the still encoded values of all comments with `key`, see [`Self::add_raw`]
+    pub fn raw_values(&self, key: impl AsRef<str>) -> impl Iterator<Item = &str> {
+        self.find_comments(key).map(|it| it.value.as_str())
+    }
+    /// mutable access to every comment, to edit keys or values in place
+    pub fn comments_mut(&mut self) -> impl Iterator<Item = &mut Comment> {
+        self.comments.iter_mut()
+    }
     pub fn find_comments(&self, key: impl AsRef<str>) -> impl Iterator<Item = &Comment> {
         self.comments
             .iter()
             .filter(move |it| it.key.eq_ignore_ascii_case(key.as_ref()))
     }
+    /// the value of the first comment with `key`
+    pub fn get_first(&self, key: impl AsRef<str>) -> Option<&str> {
+        self.find_comments(key).next().map(|it| it.value.as_str())
+    }
+    pub fn contains_key(&self, key: impl AsRef<str>) -> bool {
+        self.comments
+            .iter()
+            .any(|it| it.key.eq_ignore_ascii_case(key.as_ref()))
+    }
+    pub fn count(&self, key: impl AsRef<str>) -> usize {
+        self.find_comments(key).count()
+    }
+    /// every distinct key once, in the casing and order it first appears in
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.comments
+            .iter()
+            .map(|it| it.key.as_str())
+            .unique_by(|key| key.to_ascii_uppercase())
+    }
+    /// every key, that appears more than once, with the number of its comments
+    ///
+    /// keys are compared case-insensitively and returned like in [`Self::keys`]
+    pub fn duplicate_keys(&self) -> Vec<(String, usize)> {
+        self.keys()
+            .map(|key| (key.to_owned(), self.count(key)))
+            .filter(|(_, count)| *count > 1)
+            .collect_vec()
+    }
     pub fn remove_first(&mut self, key: impl AsRef<str>) -> Option<Comment> {
         let element =
             self.comments.iter().enumerate().find_map(|(i, comment)| {
@@ -207,123 +587,1591 @@ impl VorbisComment {
         self.comments
             .retain(|it| !it.key.eq_ignore_ascii_case(key.as_ref()));
     }
-
-    /// reads opus metadata from `from`, updates the [`OpusTags`] and writes the whole updated stream to `to`
-    fn update_opus_tags(&self, mut from: impl Read, mut to: impl Write) -> Result<(), Error> {
-        let mut iter = OggPage::iterate_read(&mut from);
-        let head_ogg = iter
-            .next()
-            .ok_or_else(|| Error::MalformedData("missing first ogg_packet".to_owned()))??;
-        let mut tags_ogg = iter
+    /// replaces all comments with `key` by one comment per value, appended in order.
+    ///
+    /// returns the previous values of `key`
+    pub fn set_all<V: Into<String>>(
+        &mut self,
+        key: impl Into<String>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Vec<String> {
+        let key = key.into();
+        let (previous, comments) = std::mem::take(&mut self.comments)
+            .into_iter()
+            .partition::<Vec<_>, _>(|it| it.key.eq_ignore_ascii_case(&key));
+        self.comments = comments;
+        self.append_all(values.into_iter().map(|value| (key.clone(), value.into())));
+        previous.into_iter().map(|it| it.value).collect_vec()
+    }
+    /// keeps only the comments, for which `f` returns `true`
+    pub fn retain<F: FnMut(&Comment) -> bool>(&mut self, f: F) {
+        self.comments.retain(f);
+    }
+    /// removes all comments with an empty value
+    pub fn remove_empty_values(&mut self) {
+        self.retain(|it| !it.value.is_empty());
+    }
+    /// replaces the value of the first comment with `key` and removes all other comments with it.
+    ///
+    /// appends a new comment, when there is none
+    fn set_single(&mut self, key: &str, value: String) {
+        let mut found = false;
+        self.comments.retain_mut(|comment| {
+            if !comment.key.eq_ignore_ascii_case(key) {
+                return true;
+            }
+            if found {
+                return false;
+            }
+            found = true;
+            comment.value.clone_from(&value);
+            true
+        });
+        if !found {
+            self.add_comment((key, value));
+        }
+    }
+    /// the year of the `DATE` or otherwise the `YEAR` comment.
+    ///
+    /// only the leading four digits are parsed, so `2004` and `2004-05-01` both result in 2004
+    pub fn year(&self) -> Option<i32> {
+        fn parse_year(value: &str) -> Option<i32> {
+            let (year, rest) = value.split_at_checked(4)?;
+            let is_year = year.bytes().all(|it| it.is_ascii_digit())
+                && !rest.starts_with(|c: char| c.is_ascii_digit());
+            is_year.then(|| year.parse().ok()).flatten()
+        }
+        self.find_comments("DATE")
+            .chain(self.find_comments("YEAR"))
+            .find_map(|it| parse_year(&it.value))
+    }
+    /// sets `DATE` to `year`, see [`Self::year`]
+    pub fn set_year(&mut self, year: i32) {
+        self.set_single("DATE", format!("{year:04}"));
+    }
+    /// parses the `n` or `n/total` value of `number_key`, a missing total is read from the first of `total_keys`.
+    ///
+    /// a total of zero is treated as unknown
+    fn number_and_total(
+        &self,
+        number_key: &str,
+        total_keys: &[&str],
+    ) -> Option<(u32, Option<u32>)> {
+        let value = self.get_first(number_key)?;
+        let (number, total) = match value.split_once('/') {
+            // an empty total like in `2/` falls back to the separate keys
+            Some((number, total)) => (number, Some(total).filter(|it| !it.trim().is_empty())),
+            None => (value, None),
+        };
+        let number = number.trim().parse().ok()?;
+        let total = total
+            .into_iter()
+            .chain(total_keys.iter().filter_map(|key| self.get_first(key)))
             .next()
-            .ok_or_else(|| Error::MalformedData("missing second ogg_packet".to_owned()))??;
+            .and_then(|it| it.trim().parse().ok())
+            .filter(|&it| it != 0);
+        Some((number, total))
+    }
+    /// the chapter marks ordered by their number, a mark with an invalid time is skipped
+    pub fn chapters(&self) -> Vec<Chapter> {
+        fn parse_time(value: &str) -> Option<Duration> {
+            let (hours, rest) = value.split_once(':')?;
+            let (minutes, seconds) = rest.split_once(':')?;
+            let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, "0"));
+            let seconds = hours.parse::<u64>().ok()? * 3600
+                + minutes.parse::<u64>().ok()? * 60
+                + seconds.parse::<u64>().ok()?;
+            let nanos = format!("{fraction:0<9}").get(..9)?.parse().ok()?;
+            Some(Duration::new(seconds, nanos))
+        }
+        self.comments
+            .iter()
+            .filter_map(|comment| {
+                let number = comment
+                    .key
+                    .get(..7)
+                    .filter(|it| it.eq_ignore_ascii_case("CHAPTER"))
+                    .and_then(|_| comment.key[7..].parse::<u32>().ok())?;
+                let start = parse_time(&comment.value)?;
+                let name = self
+                    .get_first(format!("{}NAME", comment.key))
+                    .map(str::to_owned);
+                Some((number, Chapter { start, name }))
+            })
+            .sorted_by_key(|(number, _)| *number)
+            .map(|(_, chapter)| chapter)
+            .collect_vec()
+    }
+    /// replaces all chapter marks with `chapters`, numbered from `CHAPTER000`
+    pub fn set_chapters(&mut self, chapters: &[Chapter]) {
+        self.retain(|comment| {
+            let key = comment.key.to_ascii_uppercase();
+            let number = key
+                .strip_prefix("CHAPTER")
+                .map(|it| it.strip_suffix("NAME").unwrap_or(it));
+            !number.is_some_and(|it| !it.is_empty() && it.bytes().all(|it| it.is_ascii_digit()))
+        });
+        for (i, chapter) in chapters.iter().enumerate() {
+            let seconds = chapter.start.as_secs();
+            self.add_comment((
+                format!("CHAPTER{i:03}"),
+                format!(
+                    "{:02}:{:02}:{:02}.{:03}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60,
+                    chapter.start.subsec_millis()
+                ),
+            ));
+            if let Some(name) = &chapter.name {
+                self.add_comment((format!("CHAPTER{i:03}NAME"), name.as_str()));
+            }
+        }
+    }
+    /// the track number and the total number of tracks.
+    ///
+    /// reads either the combined `TRACKNUMBER=n/total` or the separate `TRACKNUMBER`
+    /// and `TRACKTOTAL` or `TOTALTRACKS` comments. zero padded values like `01` are accepted
+    pub fn track(&self) -> Option<(u32, Option<u32>)> {
+        self.number_and_total("TRACKNUMBER", &["TRACKTOTAL", "TOTALTRACKS"])
+    }
+    /// the disc number and the total number of discs, like [`Self::track`]
+    /// with `DISCNUMBER` and `DISCTOTAL` or `TOTALDISCS`
+    pub fn disc(&self) -> Option<(u32, Option<u32>)> {
+        self.number_and_total("DISCNUMBER", &["DISCTOTAL", "TOTALDISCS"])
+    }
+    /// sets `TRACKNUMBER` to `number` and `TRACKTOTAL` to `total`.
+    ///
+    /// a previous `TOTALTRACKS` is removed, so [`Self::track`] returns the new values
+    pub fn set_track(&mut self, number: u32, total: Option<u32>) {
+        self.set_single("TRACKNUMBER", number.to_string());
+        self.remove_all("TOTALTRACKS");
+        match total {
+            Some(total) => self.set_single("TRACKTOTAL", total.to_string()),
+            None => self.remove_all("TRACKTOTAL"),
+        }
+    }
+
+    /// parses the vendor string and the `ENCODER` and `ENCODED-BY` comments
+    pub fn encoder_info(&self) -> EncoderInfo {
+        let (library, version) = if let Some(version) = self.vendor.strip_prefix("Lavf") {
+            (EncoderLibrary::Libavformat, version)
+        } else if let Some(version) = self.vendor.strip_prefix("libopus ") {
+            (EncoderLibrary::Libopus, version)
+        } else {
+            (EncoderLibrary::Other(self.vendor.clone()), "")
+        };
+        let version = version.trim();
+        EncoderInfo {
+            library,
+            version: (!version.is_empty()).then(|| version.to_owned()),
+            encoder: self.get_first("ENCODER").map(str::to_owned),
+            encoded_by: self.get_first("ENCODED-BY").map(str::to_owned),
+        }
+    }
+    /// the first `ENCODER` comment
+    pub fn encoder(&self) -> Option<&str> {
+        self.get_first("ENCODER")
+    }
+    /// stamps `name_version` as the only `ENCODER` comment and also as the vendor string, when `update_vendor` is set
+    pub fn set_encoder(&mut self, name_version: &str, update_vendor: bool) {
+        self.set_single("ENCODER", name_version.to_owned());
+        if update_vendor {
+            name_version.clone_into(&mut self.vendor);
+        }
+    }
+    /// a hash of the comments, that stays the same across runs and versions of this crate.
+    ///
+    /// the comments are sorted and their keys uppercased before hashing, so reordering comments or changing the case of a key
+    /// doesn't change the hash, but editing, adding or removing a value does. The vendor string is ignored
+    pub fn content_hash(&self) -> u64 {
+        const HASH: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+        let mut digest = HASH.digest();
+        for (key, value) in self
+            .comments
+            .iter()
+            .map(|it| (it.key.to_ascii_uppercase(), it.value.as_str()))
+            .sorted_unstable()
+        {
+            // length prefixes keep the boundaries between keys and values unambiguous
+            for part in [key.as_bytes(), value.as_bytes()] {
+                digest.update(&(part.len() as u64).to_le_bytes());
+                digest.update(part);
+            }
+        }
+        digest.finalize()
+    }
+    /// strips leading and trailing ASCII whitespace from every value
+    pub fn trim_values(&mut self) {
+        for comment in &mut self.comments {
+            let trimmed = comment
+                .value
+                .trim_matches(|c: char| c.is_ascii_whitespace());
+            if trimmed.len() != comment.value.len() {
+                comment.value = trimmed.to_owned();
+            }
+        }
+    }
+    /// moves the comments with a key in `template` to the front, in the order of `template`.
+    ///
+    /// keys are compared case-insensitively, comments with the same key and the comments not in `template` keep their order
+    pub fn reorder_to(&mut self, template: &[&str]) {
+        self.comments.sort_by_key(|comment| {
+            template
+                .iter()
+                .position(|key| key.eq_ignore_ascii_case(&comment.key))
+                .unwrap_or(template.len())
+        });
+    }
+    /// converts every key to `case`
+    pub fn normalize_keys(&mut self, case: KeyCase) {
+        for comment in &mut self.comments {
+            match case {
+                KeyCase::Upper => comment.key.make_ascii_uppercase(),
+                KeyCase::Lower => comment.key.make_ascii_lowercase(),
+            }
+        }
+    }
+    /// converts every key to uppercase and replaces the aliases in [`KEY_ALIASES`] with their canonical key
+    pub fn canonicalize_keys(&mut self) {
+        self.normalize_keys(KeyCase::Upper);
+        for comment in &mut self.comments {
+            if let Some((_, canonical)) =
+                KEY_ALIASES.iter().find(|(alias, _)| *alias == comment.key)
+            {
+                (*canonical).clone_into(&mut comment.key);
+            }
+        }
+    }
+    /// every comment as a `KEY=VALUE` line, in the escaped text format of `vorbiscomment -e`
+    pub fn to_lines(&self) -> Vec<String> {
+        self.comments
+            .iter()
+            .map(|it| escape_line(&format!("{}={}", it.key, it.value)))
+            .collect_vec()
+    }
+    /// parses the output of [`Self::to_lines`], with the [`DEFAULT_VENDOR`] as vendor
+    ///
+    /// # Errors
+    /// when a line contains an invalid escape sequence or no `=`
+    pub fn from_lines<Iter: IntoIterator>(lines: Iter) -> Result<Self, error::Error>
+    where
+        Iter::Item: AsRef<str>,
+    {
+        let mut tags = Self::default();
+        for line in lines {
+            tags.add_comment(Comment::parse(&unescape_line(line.as_ref())?)?);
+        }
+        Ok(tags)
+    }
+
+    /// reads the head and comment pages from `from` and returns them serialized, with the comment page(s) replaced by `self`
+    ///
+    /// `from` is left positioned at the first audio page
+    fn rewrite_header_pages(
+        &self,
+        from: &mut impl Read,
+        options: WriteOptions,
+    ) -> Result<HeaderRewrite, Error> {
+        let mut iter = OggPage::iterate_read(from);
+        let head_ogg = iter.next().ok_or(Error::NoMoreData)??;
+        // the comment header ends with the first segment shorter than 255 bytes, which may be several pages later
+        let mut tags_oggs: Vec<OggPage> = Vec::new();
+        while !tags_oggs.last().is_some_and(|page| {
+            page.segment_table()
+                .iter()
+                .any(|segment| segment.len() < u8::MAX as usize)
+        }) {
+            let eof_err = if tags_oggs.is_empty() {
+                Error::NoMoreData
+            } else {
+                Error::UnexpectedEoF
+            };
+            tags_oggs.push(iter.next().ok_or(eof_err)??);
+        }
         drop(iter);
 
         // validate current data
-        let _tags = Self::from(&tags_ogg, TAGS_MAGIC_STR)?;
+        let tags_packet = tags_oggs.iter().map(OggPage::payload).concat();
+        expect_comment_header(&tags_packet)?;
+        let _tags = Self::from_bytes(&tags_packet, TAGS_MAGIC_STR)?;
         let _head = OpusHead::from(&head_ogg)?;
 
-        let table = self
-            .to_bytes(TAGS_MAGIC_STR)
-            .chunks(255)
-            .map(<[u8]>::to_vec)
-            .collect_vec();
-        tags_ogg.set_segment_table(table).unwrap();
-
-        head_ogg.write_to(&mut to)?;
-        tags_ogg.write_to(&mut to)?;
+        let first = &tags_oggs[0];
+        let last = &tags_oggs[tags_oggs.len() - 1];
+        let segments_per_page = options.segments_per_page();
+        let segments = lace(&self.to_bytes(TAGS_MAGIC_STR)?);
+        let page_count = segments.len().div_ceil(segments_per_page);
 
-        std::io::copy(&mut from, &mut to)?;
+        let mut buf = Vec::new();
+        head_ogg.write_to(&mut buf)?;
+        for (i, chunk) in segments.chunks(segments_per_page).enumerate() {
+            let (header_type, granule_position) = match (i == 0, i + 1 == page_count) {
+                (true, true) => (first.header_type, last.granule_position),
+                (true, false) => (first.header_type, NO_GRANULE_POSITION),
+                (false, true) => (HeaderType::CONTINUATION, last.granule_position),
+                // no packet ends on this page
+                (false, false) => (HeaderType::CONTINUATION, NO_GRANULE_POSITION),
+            };
+            OggPage::new(
+                header_type,
+                granule_position,
+                first.bitstream_serial_number,
+                first.page_sequence_number + i as u32,
+                chunk.to_vec(),
+            )
+            .expect("chunks are limited to 255 segments")
+            .write_to(&mut buf)?;
+        }
+        Ok(HeaderRewrite {
+            bytes: buf,
+            bitstream_serial_number: first.bitstream_serial_number,
+            old_next_page: first.page_sequence_number + tags_oggs.len() as u32,
+            new_next_page: first.page_sequence_number + page_count as u32,
+        })
+    }
+    /// reads opus metadata from `from`, updates the [`OpusTags`] and writes the whole updated stream to `to`
+    fn update_opus_tags(
+        &self,
+        mut from: impl Read,
+        mut to: impl Write,
+        options: WriteOptions,
+    ) -> Result<(), Error> {
+        let header = self.rewrite_header_pages(&mut from, options)?;
+        to.write_all(&header.bytes)?;
+        std::io::copy(&mut header.tail(from), &mut to)?;
         Ok(())
     }
+    /// returns the whole stream `from` with its tags replaced by `self`, without writing it anywhere
+    ///
+    /// # Errors
+    /// when `from` doesn't contain a valid opus stream or can't be read
+    pub fn preview_update(&self, from: impl Read) -> Result<Vec<u8>, Error> {
+        self.preview_update_with(from, WriteOptions::default())
+    }
+    /// like [`Self::preview_update`], but the comment pages are laid out according to `options`
+    ///
+    /// # Errors
+    /// when `from` doesn't contain a valid opus stream or can't be read
+    pub fn preview_update_with(
+        &self,
+        from: impl Read,
+        options: WriteOptions,
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.update_opus_tags(from, &mut buf, options)?;
+        Ok(buf)
+    }
+    /// lazily rewrites the tags of the opus stream `from`.
+    ///
+    /// The returned reader yields the original head page, the comment page(s) of `self` and then the untouched rest of `from`.
+    /// Nothing is read from `from` until the first read.
+    /// Errors while parsing the header pages are reported as [`std::io::Error`] by that first read.
+    pub fn retag_stream<'a>(&'a self, from: impl Read + 'a) -> impl Read + 'a {
+        MultiChain::new(std::iter::once(from).flat_map(move |mut from| {
+            match self.rewrite_header_pages(&mut from, WriteOptions::default()) {
+                Ok(mut header) => vec![
+                    Either::Left(Either::Left(std::io::Cursor::new(std::mem::take(
+                        &mut header.bytes,
+                    )))),
+                    Either::Right(header.tail(from)),
+                ],
+                Err(err) => vec![Either::Left(Either::Right(FailingRead::new(err.into())))],
+            }
+        }))
+    }
+    /// writes `self` as the comment header of the opus file at `path`.
+    ///
+    /// the file is updated in place, when the comment pages keep their length, otherwise it is replaced by a rewritten copy.
+    /// a symlink at `path` is replaced by a regular file in that case, see [`SymlinkBehavior`] to change that.
+    /// unlike the replacement, the update in place isn't atomic, see [`Self::update_in_place`]
+    ///
+    /// # Errors
+    /// when the file doesn't contain a valid opus stream or reading/writing fails
     #[momo::momo]
     pub fn write_opus_file(&self, path: impl AsRef<Path>) -> Result<(), Error> {
-        let file = std::fs::File::open(path).expect("file not found");
-        let tmp_name = path.file_name().unwrap().to_string_lossy();
-        let mut tmp_name =
-            common::io::TmpFile::new_empty(path.with_file_name(format!(".{tmp_name}"))).unwrap();
-        // .expect("tmp file already exists");
-        let tmp_file = std::fs::File::options()
-            .read(true)
-            .write(true)
-            .open(&tmp_name)
-            .unwrap();
+        self.write_opus_file_with_tmp(path, path.parent().unwrap_or_else(|| Path::new("")))
+    }
+    /// removes all comments and the vendor string from the opus file at `path`, by writing [`Self::stripped`] to it.
+    ///
+    /// the audio data is left untouched
+    ///
+    /// # Errors
+    /// see [`Self::write_opus_file`]
+    pub fn strip_tags_file(path: impl AsRef<Path>) -> Result<(), Error> {
+        Self::stripped("").write_opus_file(path)
+    }
+    /// like [`Self::write_opus_file`], but the comment pages are laid out according to `options`
+    ///
+    /// # Errors
+    /// see [`Self::write_opus_file`]
+    /// when `path` is a symlink and `options` use [`SymlinkBehavior::Reject`]
+    #[momo::momo]
+    pub fn write_opus_file_with(
+        &self,
+        path: impl AsRef<Path>,
+        options: WriteOptions,
+    ) -> Result<(), Error> {
+        self.write_file(
+            path,
+            path.parent().unwrap_or_else(|| Path::new("")),
+            options,
+        )
+    }
+    /// like [`Self::write_opus_file`], but the temporary file is created in `tmp_dir` instead of next to `path`.
+    ///
+    /// when `tmp_dir` is on another filesystem, the result is copied next to `path` and renamed from there
+    ///
+    /// # Errors
+    /// see [`Self::write_opus_file`]
+    /// when the temporary file can't be created in `tmp_dir`
+    #[momo::momo]
+    pub fn write_opus_file_with_tmp(
+        &self,
+        path: impl AsRef<Path>,
+        tmp_dir: &Path,
+    ) -> Result<(), Error> {
+        self.write_file(path, tmp_dir, WriteOptions::default())
+    }
+    fn write_file(&self, path: &Path, tmp_dir: &Path, options: WriteOptions) -> Result<(), Error> {
+        let canonical_path;
+        let path = if std::fs::symlink_metadata(path)?.file_type().is_symlink() {
+            match options.symlinks {
+                SymlinkBehavior::Replace => path,
+                SymlinkBehavior::Follow => {
+                    canonical_path = std::fs::canonicalize(path)?;
+                    &canonical_path
+                }
+                SymlinkBehavior::Reject => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{} is a symlink", path.display()),
+                    )
+                    .into())
+                }
+            }
+        } else {
+            path
+        };
+        // only opened for writing, when it can be updated in place
+        let mut file = std::fs::File::open(path)?;
+        if let Some(bytes) = self.in_place_bytes(&mut file, options)? {
+            match std::fs::File::options().write(true).open(path) {
+                Ok(mut target) => {
+                    target.write_all(&bytes)?;
+                    return Ok(());
+                }
+                // a read-only file can still be replaced, when its directory is writable
+                Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        file.rewind()?;
+        let file_name = path.file_name().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} doesn't name a file", path.display()),
+            )
+        })?;
+        let tmp_name = format!(".{}", file_name.to_string_lossy());
+        let mut tmp_file = common::io::TmpFile::new_empty(tmp_dir.join(&tmp_name))?;
 
-        self.update_opus_tags(file, tmp_file)?;
+        self.update_opus_tags(
+            file,
+            std::fs::File::options().write(true).open(&tmp_file)?,
+            options,
+        )?;
 
-        std::fs::remove_file(path)?;
-        std::fs::rename(&tmp_name, path).unwrap(); // this shouldn't fail, because then the file whill be lost
-        tmp_name.was_removed(); // mark file to not autoremove
+        match std::fs::rename(&tmp_file, path) {
+            Ok(()) => tmp_file.was_removed(), // mark file to not autoremove
+            Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+                // renaming fails across filesystems, so the result is copied next to `path` first,
+                // which keeps the replacement atomic, the tmp file is removed afterwards
+                let mut sibling = common::io::TmpFile::new_empty(path.with_file_name(&tmp_name))?;
+                std::fs::copy(&tmp_file, &sibling)?;
+                std::fs::rename(&sibling, path)?;
+                sibling.was_removed();
+            }
+            Err(err) => return Err(err.into()),
+        }
 
         Ok(())
     }
+    /// overwrites the comment page of `file` directly, if the new pages have exactly the same length as the old ones.
+    ///
+    /// returns `false` without modifying `file`, when the length differs.
+    /// the new pages are written at once, but that isn't atomic, so a failing write can leave `file` partially updated
+    ///
+    /// # Errors
+    /// when `file` doesn't contain a valid opus stream or reading/writing fails
+    pub fn update_in_place(&self, file: &mut std::fs::File) -> Result<bool, Error> {
+        self.update_in_place_with(file, WriteOptions::default())
+    }
+    fn update_in_place_with(
+        &self,
+        file: &mut std::fs::File,
+        options: WriteOptions,
+    ) -> Result<bool, Error> {
+        let Some(bytes) = self.in_place_bytes(file, options)? else {
+            return Ok(false);
+        };
+        file.rewind()?;
+        file.write_all(&bytes)?;
+        Ok(true)
+    }
+    /// the complete new header pages of `file`, if they can replace the old ones in place
+    fn in_place_bytes(
+        &self,
+        file: &mut std::fs::File,
+        options: WriteOptions,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        file.rewind()?;
+        let mut data = CountingRead::new(&mut *file);
+        let header = self.rewrite_header_pages(&mut data, options)?;
+        // a different number of pages would require renumbering the following pages
+        if header.bytes.len() as u64 != data.count || header.old_next_page != header.new_next_page {
+            return Ok(None);
+        }
+        Ok(Some(header.bytes))
+    }
+    /// writes `self` to every file in `paths` with [`Self::write_opus_file`].
+    ///
+    /// A failing file doesn't abort the batch, instead every path is returned with its result.
+    pub fn write_opus_files(
+        &self,
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> Vec<(PathBuf, Result<(), Error>)> {
+        paths
+            .into_iter()
+            .map(|path| {
+                let result = self.write_opus_file(&path);
+                (path, result)
+            })
+            .collect_vec()
+    }
 
-    fn to_bytes(&self, magic_str: &[u8]) -> Vec<u8> {
-        let mut buf = Vec::new();
+    /// returns the number of pages the comment header of `self` occupies,
+    /// when each page holds at most `max_page_payload` bytes, see [`WriteOptions::max_page_payload`]
+    pub fn page_count(&self, max_page_payload: usize) -> usize {
+        let packet_len = self.packet_len(TAGS_MAGIC_STR);
+        // a packet with a multiple of 255 bytes is terminated by an empty segment, see [`lace`]
+        let segments = packet_len / u8::MAX as usize + 1;
+        let options = WriteOptions {
+            max_page_payload,
+            ..Default::default()
+        };
+        segments.div_ceil(options.segments_per_page())
+    }
+
+    /// serializes `self` as a Vorbis comment header packet, including the trailing framing bit
+    ///
+    /// [spec](https://xiph.org/vorbis/doc/Vorbis_I_spec.html#x1-820005)
+    ///
+    /// # Errors
+    /// when a comment is too long to be encoded, see [`Error::ValueTooLong`]
+    pub fn to_vorbis_bytes(&self) -> Result<Vec<u8>, error::Error> {
+        let mut buf = self.to_bytes(VORBIS_MAGIC_STR)?;
+        buf.push(VORBIS_FRAMING_BIT);
+        Ok(buf)
+    }
+    /// parses the comment header on `page`, which may be either an opus or a vorbis comment header
+    ///
+    /// # Errors
+    /// when the payload of `page` is no valid comment header of either kind
+    pub fn from_any(page: &OggPage) -> Result<(Self, MagicKind), error::Error> {
+        let payload = page.payload();
+        if payload.starts_with(VORBIS_MAGIC_STR) {
+            Self::from_vorbis_bytes(&payload).map(|it| (it, MagicKind::Vorbis))
+        } else {
+            Self::from_bytes(&payload, TAGS_MAGIC_STR).map(|it| (it, MagicKind::Opus))
+        }
+    }
+    /// parses the comments of the opus comment header on `page` one at a time, so a consumer can stop early.
+    ///
+    /// the iterator ends after the first error
+    pub fn iter_from(page: &OggPage) -> impl Iterator<Item = Result<Comment, error::Error>> {
+        let payload = page.payload();
+        let mut header = Some(comments_start(&payload));
+        let (mut index, mut count, mut offset) = (0, 0, 0);
+        std::iter::from_fn(move || {
+            match header.take() {
+                Some(Ok((header_count, first_offset))) => {
+                    (count, offset) = (header_count, first_offset);
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                None => {}
+            }
+            if index >= count {
+                return None;
+            }
+            let mut buf = &payload[offset..];
+            let comment = read_comment(&mut buf, index, offset, false);
+            index = if comment.is_ok() { index + 1 } else { count };
+            offset = payload.len() - buf.len();
+            Some(comment)
+        })
+    }
+    /// parses the opus comment header on `page` and returns the byte range of every comment in its payload.
+    ///
+    /// each range starts at the length prefix of its comment
+    ///
+    /// # Errors
+    /// when the payload of `page` is no valid comment header
+    pub fn from_with_offsets(page: &OggPage) -> Result<(Self, Vec<Range<usize>>), error::Error> {
+        let payload = page.payload();
+        Self::parse_packet(
+            &mut payload.as_slice(),
+            TAGS_MAGIC_STR,
+            ParseOptions::default(),
+        )
+        .map(|(it, _, ranges)| (it, ranges))
+    }
+    /// parses a Vorbis comment header packet, see [`Self::to_vorbis_bytes`]
+    ///
+    /// # Errors
+    /// when `buf` isn't a valid comment header or is missing the framing bit
+    pub fn from_vorbis_bytes(mut buf: &[u8]) -> Result<Self, error::Error> {
+        let (comment, _) =
+            Self::from_bytes_with(&mut buf, VORBIS_MAGIC_STR, ParseOptions::default())?;
+        require!(
+            buf.first()
+                .is_some_and(|framing| framing & VORBIS_FRAMING_BIT != 0),
+            error::Error::MalformedData("missing framing bit after vorbis comments".to_owned())
+        );
+        Ok(comment)
+    }
+
+    fn to_bytes(&self, magic_str: &[u8]) -> Result<Vec<u8>, error::Error> {
+        let packet_len = self.packet_len(magic_str);
+        let mut buf = Vec::with_capacity(packet_len);
         buf.extend(magic_str);
-        write_length_encode_str(&mut buf, &self.vendor).unwrap();
+        write_length_encode_str(&mut buf, &self.vendor)?;
         buf.extend((self.comments.len() as u32).to_le_bytes());
         for comment in &self.comments {
-            write_length_encode_str(&mut buf, &format!("{}={}", comment.key, comment.value))
-                .unwrap();
+            write_length_encode_str(&mut buf, &format!("{}={}", comment.key, comment.value))?;
         }
-        buf
+        debug_assert_eq!(
+            packet_len,
+            buf.len(),
+            "packet_len needs to match the layout"
+        );
+        Ok(buf)
     }
-    /// [spec](https://wiki.xiph.org/OggOpus#Comment_Header)
-    fn from(ogg_head: &OggPage, magic_str: &[u8]) -> Result<Self, error::Error> {
-        assert_eq!(ogg_head.granule_position, 0, "granule needs to be zero");
-
-        let all_seg_len = ogg_head.segment_table().iter().map(Vec::len).sum::<usize>();
+    /// the length of the packet written by [`Self::to_bytes`]
+    fn packet_len(&self, magic_str: &[u8]) -> usize {
+        let comments_len = self
+            .comments
+            .iter()
+            .map(|comment| 4 + comment.key.len() + 1 + comment.value.len())
+            .sum::<usize>();
+        magic_str.len() + 4 + self.vendor.len() + 4 + comments_len
+    }
+    /// parses the comment header packet `buf`
+    fn from_bytes(mut buf: &[u8], magic_str: &[u8]) -> Result<Self, error::Error> {
+        Self::from_bytes_with(&mut buf, magic_str, ParseOptions::default()).map(|(it, _)| it)
+    }
+    /// parses the comment header packet at the start of `buf`, with the tolerance given by `options`.
+    ///
+    /// also returns `true`, if the packet ended before the declared number of comments, see [`ParseOptions::recount_comments`].
+    /// `buf` is advanced to the end of the parsed data
+    fn from_bytes_with(
+        buf: &mut &[u8],
+        magic_str: &[u8],
+        options: ParseOptions,
+    ) -> Result<(Self, bool), error::Error> {
+        Self::parse_packet(buf, magic_str, options).map(|(it, truncated, _)| (it, truncated))
+    }
+    /// like [`Self::from_bytes_with`], but also returns the range of every comment in the packet,
+    /// from the start of its length prefix to its end
+    fn parse_packet(
+        buf: &mut &[u8],
+        magic_str: &[u8],
+        options: ParseOptions,
+    ) -> Result<(Self, bool, Vec<Range<usize>>), error::Error> {
+        let packet_len = buf.len();
         require!(
-            all_seg_len >= 12,
+            buf.len() >= 12,
             error::Error::MalformedData(format!(
-                "comment packet needs to have a length of at least 12, but got {all_seg_len}"
+                "comment packet needs to have a length of at least 12, but got {}",
+                buf.len()
             ))
         );
-        let mut buf = MultiChain::new(ogg_head.segment_table().iter().map(std::vec::Vec::as_slice));
 
-        error::Error::expect_starts_with_reader(&mut buf, magic_str)?;
+        error::Error::expect_starts_with_reader(buf, magic_str)?;
 
-        let vendor = read_length_encode_str(&mut buf)?;
-        let number_tags = read_u32(&mut buf)?;
+        let vendor = read_length_encode_str(buf)?;
+        let number_tags = read_u32(buf)?;
 
-        let mut comments = Vec::with_capacity(number_tags as usize);
-        for _ in 0..number_tags {
-            let read = read_length_encode_str(&mut buf)?;
-            let (key, value) = read.splitn(2, '=').collect_tuple().ok_or_else(|| {
-                error::Error::MalformedData(format!("missing seperator '=' in {read:?}"))
-            })?;
-            comments.push((key, value).into());
+        // the declared count is untrusted, but each comment needs at least its length prefix
+        let capacity = (number_tags as usize).min(buf.len() / 4);
+        let mut comments = Vec::with_capacity(capacity);
+        let mut ranges = Vec::with_capacity(capacity);
+        for index in 0..number_tags {
+            if options.recount_comments && buf.is_empty() {
+                return Ok((Self { vendor, comments }, true, ranges));
+            }
+            let offset = packet_len - buf.len();
+            comments.push(read_comment(buf, index, offset, options.lossy_utf8)?);
+            ranges.push(offset..packet_len - buf.len());
         }
-        Ok(Self { vendor, comments })
+        Ok((Self { vendor, comments }, false, ranges))
     }
 }
+/// returns the number of comments in the opus comment header `packet` and the offset of the first one
+fn comments_start(packet: &[u8]) -> Result<(u32, usize), error::Error> {
+    expect_comment_header(packet)?;
+    let mut buf = &packet[TAGS_MAGIC_STR.len()..];
+    read_length_encode_str(&mut buf)?;
+    let count = read_u32(&mut buf)?;
+    Ok((count, packet.len() - buf.len()))
+}
+/// reads the length prefixed comment number `index`, that starts at `offset` in its packet
+fn read_comment(
+    buf: &mut &[u8],
+    index: u32,
+    offset: usize,
+    lossy_utf8: bool,
+) -> Result<Comment, error::Error> {
+    let length = read_u32(buf)?;
+    read_str(buf, length, lossy_utf8)
+        .and_then(|read| Comment::parse(&read))
+        .map_err(|err| error::Error::MalformedComment {
+            index,
+            offset,
+            length,
+            source: Box::new(err),
+        })
+}
 
-const TAGS_MAGIC_STR: &[u8] = b"OpusTags";
-#[derive(Debug, PartialEq, Eq)]
-pub struct OpusMeta {
-    pub head: OpusHead,
-    pub tags: VorbisComment,
+pub const TAGS_MAGIC_STR: &[u8] = b"OpusTags";
+const GZIP_MAGIC_STR: &[u8] = b"\x1f\x8b";
+const XZ_MAGIC_STR: &[u8] = b"\xfd7zXZ\x00";
+const VORBIS_MAGIC_STR: &[u8] = b"\x03vorbis";
+const VORBIS_FRAMING_BIT: u8 = 0x01;
+/// the codec of a comment header, see [`VorbisComment::from_any`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MagicKind {
+    /// starts with [`TAGS_MAGIC_STR`]
+    Opus,
+    /// starts with `\x03vorbis` and ends with a framing bit
+    Vorbis,
 }
-impl OpusMeta {
-    /// reads `Self` from `path`
-    ///
-    /// # Errors
-    /// when `data` doesn't start with a valid `OpusHead` and `VorbisComment`
+/// the role of a packet in an opus stream, see [`PacketKind::label`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PacketKind {
+    IdHeader,
+    CommentHeader,
+    /// every packet after the headers
+    Audio,
+}
+impl PacketKind {
+    /// classifies the packet at `index` of an opus stream by its position and magic string
+    pub fn classify(index: usize, data: &[u8]) -> Self {
+        match index {
+            0 if data.starts_with(HEAD_MAGIC_STR) => Self::IdHeader,
+            1 if data.starts_with(TAGS_MAGIC_STR) => Self::CommentHeader,
+            _ => Self::Audio,
+        }
+    }
+    /// labels each of `packets` with [`Self::classify`], so callers can stop at the first audio packet
+    pub fn label(
+        packets: impl IntoIterator<Item = Result<Packet, error::Error>>,
+    ) -> impl Iterator<Item = Result<(Self, Packet), error::Error>> {
+        packets
+            .into_iter()
+            .enumerate()
+            .map(|(i, packet)| packet.map(|packet| (Self::classify(i, &packet.data), packet)))
+    }
+}
+/// fails with [`Error::UnexpectedPacket`], when the second packet of a stream isn't a comment header,
+/// e.g. because the audio data starts early
+fn expect_comment_header(packet: &[u8]) -> Result<(), error::Error> {
+    require!(
+        packet.starts_with(TAGS_MAGIC_STR),
+        Error::UnexpectedPacket {
+            expected: "OpusTags",
+            found: packet[..packet.len().min(TAGS_MAGIC_STR.len())].to_vec(),
+        }
+    );
+    Ok(())
+}
+/// a difference between the comments of two [`OpusMeta`], see [`OpusMeta::tag_diff`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TagChange {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+}
+/// a summary of a whole opus stream, see [`OpusMeta::stats`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StreamStats {
+    pub duration: Duration,
+    /// number of samples per channel after the pre-skip, at 48 kHz
+    pub sample_count: u64,
+    /// in bits per second, including the container overhead
+    pub average_bitrate: u64,
+    pub page_count: usize,
+}
+/// the information a player needs to play consecutive files without gaps, see [`OpusMeta::gapless_info`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GaplessInfo {
+    /// samples to discard at the start, from the [`OpusHead`]
+    pub pre_skip: u16,
+    /// samples to discard at the end, that the last packet decodes to beyond the final granule position
+    pub end_trim: u64,
+    /// samples per channel to play, at 48 kHz
+    pub total_samples: u64,
+}
+/// controls how tolerant [`OpusMeta::read_from_with`] is towards damaged or unusual streams
+///
+/// the default matches [`OpusMeta::read_from`]
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ParseOptions {
+    /// accept header pages with a wrong checksum
+    pub ignore_crc_errors: bool,
+    /// accept an ID header on a page with a nonzero granule position
+    pub allow_nonzero_head_granule: bool,
+    /// reject comment headers longer than this
+    pub max_comment_bytes: Option<usize>,
+    /// replace invalid UTF-8 in comments with [`char::REPLACEMENT_CHARACTER`] instead of failing
+    pub lossy_utf8: bool,
+    /// stop at the end of the comment packet, when it contains fewer comments than declared, instead of failing
+    pub recount_comments: bool,
+}
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            ignore_crc_errors: false,
+            allow_nonzero_head_granule: true,
+            max_comment_bytes: None,
+            lossy_utf8: false,
+            recount_comments: false,
+        }
+    }
+}
+/// controls the layout of the pages written by [`VorbisComment::write_opus_file_with`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WriteOptions {
+    /// the payload size a comment page may not exceed.
+    ///
+    /// it is rounded down to whole segments of 255 bytes, but each page contains at least one segment
+    pub max_page_payload: usize,
+    /// how a path, that is a symlink, is written to
+    pub symlinks: SymlinkBehavior,
+}
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            max_page_payload: 255 * 255,
+            symlinks: SymlinkBehavior::default(),
+        }
+    }
+}
+/// how [`VorbisComment::write_opus_file_with`] treats a path, that is a symlink
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SymlinkBehavior {
+    /// replace the symlink with a regular file, when the file can't be updated in place.
+    ///
+    /// an in place update still writes through to the linked file, and the linked file keeps its old content otherwise
+    #[default]
+    Replace,
+    /// write to the linked file, so the symlink keeps pointing to the updated file
+    Follow,
+    /// fail with an [`std::io::ErrorKind::InvalidInput`] error without writing anything
+    Reject,
+}
+impl WriteOptions {
+    /// the number of segments a comment page may contain
+    fn segments_per_page(self) -> usize {
+        (self.max_page_payload / u8::MAX as usize).clamp(1, u8::MAX as usize)
+    }
+}
+/// the rewritten header pages of a stream, see [`VorbisComment::rewrite_header_pages`]
+struct HeaderRewrite {
+    bytes: Vec<u8>,
+    bitstream_serial_number: u32,
+    /// sequence number of the first page after the headers in the original stream
+    old_next_page: u32,
+    /// sequence number of the first page after the headers in the rewritten stream
+    new_next_page: u32,
+}
+impl HeaderRewrite {
+    /// the remaining pages of `from`, renumbered when the number of comment pages changed
+    ///
+    /// the pages are read and rewritten one at a time, so the memory use doesn't depend on the length of `from`
+    fn tail<R: Read>(&self, from: R) -> impl Read {
+        if self.old_next_page == self.new_next_page {
+            return Either::Left(from);
+        }
+        let (serial, old, new) = (
+            self.bitstream_serial_number,
+            self.old_next_page,
+            self.new_next_page,
+        );
+        Either::Right(MultiChain::new(OggPage::iterate_read(from).map(
+            move |page| {
+                let buf = page.and_then(|mut page| {
+                    if page.bitstream_serial_number == serial {
+                        page.page_sequence_number = page
+                            .page_sequence_number
+                            .wrapping_sub(old)
+                            .wrapping_add(new);
+                    }
+                    let mut buf = Vec::new();
+                    page.write_to(&mut buf)?;
+                    Ok(buf)
+                });
+                match buf {
+                    Ok(buf) => Either::Left(std::io::Cursor::new(buf)),
+                    Err(err) => Either::Right(FailingRead::new(err.into())),
+                }
+            },
+        )))
+    }
+}
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpusMeta {
+    pub head: OpusHead,
+    pub tags: VorbisComment,
+}
+/// a human readable summary, similar to the output of `opusinfo`
+impl std::fmt::Display for OpusMeta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Encoded with {}", self.tags.vendor)?;
+        writeln!(f, "User comments section follows...")?;
+        for comment in &self.tags.comments {
+            writeln!(f, "\t{}={}", comment.key, comment.value)?;
+        }
+        writeln!(f, "Pre-skip: {}", self.head.pre_skip)?;
+        writeln!(f, "Playback gain: {} dB", self.head.gain.as_db())?;
+        writeln!(f, "Channels: {}", self.head.channel_count)?;
+        write!(f, "Original sample rate: {} Hz", self.head.sample_rate)
+    }
+}
+impl OpusMeta {
+    /// reads `Self` from `data`
+    ///
+    /// pages are read one at a time and only until the comment header is complete,
+    /// so this never reads past the page, on which the comment header ends
+    ///
+    /// # Errors
+    /// when `data` doesn't start with a valid `OpusHead` and `VorbisComment`
+    ///
+    /// when `data` ends before both headers are complete, [`Error::NoMoreData`] is returned, if it ended on a page boundary
+    /// and [`Error::UnexpectedEoF`] if it ended inside of a page
     pub fn read_from<R: Read>(data: R) -> Result<Self, error::Error> {
-        let mut iter = OggPage::iterate_read(data);
-        let head = OpusHead::from(
-            &iter
-                .next()
-                .ok_or_else(|| Error::MalformedData("missing first ogg_packet".to_owned()))??,
+        Self::read_from_with(data, ParseOptions::default())
+    }
+    /// reads `Self` from `data`, with the tolerance given by `options`
+    ///
+    /// # Errors
+    /// when [`Self::read_from`] errors, unless it is allowed by `options`
+    /// when the read data violates `options`
+    pub fn read_from_with<R: Read>(data: R, options: ParseOptions) -> Result<Self, error::Error> {
+        Self::read_counting_with(data, options).map(|(meta, _)| meta)
+    }
+    /// like [`Self::read_from_with`], but also returns `true`, if the comment header contained fewer comments than declared.
+    ///
+    /// this can only happen with [`ParseOptions::recount_comments`]
+    ///
+    /// # Errors
+    /// see [`Self::read_from_with`]
+    pub fn read_from_with_truncation<R: Read>(
+        data: R,
+        options: ParseOptions,
+    ) -> Result<(Self, bool), error::Error> {
+        Self::read_from_pages(
+            OggPage::iterate_read_with(data, !options.ignore_crc_errors),
+            options,
+        )
+    }
+    /// reads `Self` from the in-memory opus stream `data`
+    ///
+    /// # Errors
+    /// when [`Self::read_from`] errors
+    pub fn read_from_bytes(data: &[u8]) -> Result<Self, error::Error> {
+        Self::read_from(data)
+    }
+    /// reads `Self` from `data`, without reading past the page, on which the comment header ends.
+    ///
+    /// this is the same as [`Self::read_from`] and relies on its guarantee,
+    /// so a client fetching a file in parts only needs the start of it,
+    /// [`Self::read_from_counting`] returns how many bytes that were
+    ///
+    /// # Errors
+    /// when [`Self::read_from`] errors
+    pub fn read_headers_only(data: impl Read) -> Result<Self, error::Error> {
+        Self::read_from(data)
+    }
+    /// reads `Self` from `data` and also returns the number of bytes read, up to and including the page with the end of the comment header
+    ///
+    /// # Errors
+    /// when [`Self::read_from`] errors
+    pub fn read_from_counting<R: Read>(data: R) -> Result<(Self, u64), error::Error> {
+        Self::read_counting_with(data, ParseOptions::default())
+    }
+    fn read_counting_with<R: Read>(
+        data: R,
+        options: ParseOptions,
+    ) -> Result<(Self, u64), error::Error> {
+        let mut data = CountingRead::new(data);
+        let (meta, _) = Self::read_from_pages(
+            OggPage::iterate_read_with(&mut data, !options.ignore_crc_errors),
+            options,
         )?;
-        let tags = VorbisComment::from(
-            &iter
-                .next()
-                .ok_or_else(|| Error::MalformedData("missing second ogg_packet".to_owned()))??,
+        Ok((meta, data.count))
+    }
+    /// reads `Self` from `data` and also returns all pages, that contain the headers
+    ///
+    /// # Errors
+    /// when [`Self::read_from`] errors
+    pub fn read_with_pages<R: Read>(data: R) -> Result<(Self, Vec<OggPage>), error::Error> {
+        let mut pages = Vec::new();
+        let (meta, _) = Self::read_from_pages(
+            OggPage::iterate_read(data).inspect(|page| {
+                if let Ok(page) = page {
+                    pages.push(page.clone());
+                }
+            }),
+            ParseOptions::default(),
+        )?;
+        Ok((meta, pages))
+    }
+    /// parses the headers from `pages`, only pulling pages until the comment header is complete
+    ///
+    /// also returns `true`, if the comment header contained fewer comments than declared
+    fn read_from_pages(
+        pages: impl Iterator<Item = Result<OggPage, error::Error>>,
+        options: ParseOptions,
+    ) -> Result<(Self, bool), error::Error> {
+        // the headers are read as packets, as they aren't required to start or end on page boundaries
+        let mut iter = OggPage::packets_of(pages);
+        let head_packet = iter.next().ok_or(Error::NoMoreData)??;
+        let head = OpusHead::from_packet(&head_packet.data, head_packet.granule_position)?;
+        require!(
+            options.allow_nonzero_head_granule || head.has_zero_granule(),
+            Error::MalformedData(format!(
+                "OpusHead needs a granule position of zero, but got {}",
+                head.granule_position
+            ))
+        );
+        let tags_packet = iter.next().ok_or(Error::NoMoreData)??;
+        expect_comment_header(&tags_packet.data)?;
+        if let Some(max) = options.max_comment_bytes {
+            require!(
+                tags_packet.data.len() <= max,
+                Error::MalformedData(format!(
+                    "comment header is {} bytes long, but only {max} are allowed",
+                    tags_packet.data.len()
+                ))
+            );
+        }
+        let (tags, truncated) = VorbisComment::from_bytes_with(
+            &mut tags_packet.data.as_slice(),
             TAGS_MAGIC_STR,
+            options,
         )?;
-        Ok(Self { head, tags })
+        Ok((Self { head, tags }, truncated))
+    }
+    /// the changes of the comments from `self` to `other`, the heads and vendor strings are ignored.
+    ///
+    /// keys are compared case-insensitively and reported in the order they first appear.
+    /// values of a key present on both sides are unchanged, regardless of their order.
+    /// the remaining values are paired up in order as [`TagChange::Changed`], the surplus is added or removed
+    pub fn tag_diff(&self, other: &Self) -> Vec<TagChange> {
+        fn values_of<'a>(tags: &'a VorbisComment, key: &str) -> Vec<&'a str> {
+            tags.find_comments(key)
+                .map(|it| it.value.as_str())
+                .collect_vec()
+        }
+        let mut changes = Vec::new();
+        for key in self
+            .tags
+            .keys()
+            .chain(other.tags.keys())
+            .unique_by(|key| key.to_ascii_uppercase())
+        {
+            let mut new = values_of(&other.tags, key);
+            // drops every value, that is also in `new`, from both
+            let old = values_of(&self.tags, key)
+                .into_iter()
+                .filter(|value| {
+                    let unchanged = new.iter().position(|it| it == value);
+                    unchanged.map(|i| new.remove(i)).is_none()
+                })
+                .collect_vec();
+            let key = key.to_owned();
+            for pair in old.into_iter().zip_longest(new) {
+                changes.push(match pair {
+                    itertools::EitherOrBoth::Both(old, new) => TagChange::Changed {
+                        key: key.clone(),
+                        old: old.to_owned(),
+                        new: new.to_owned(),
+                    },
+                    itertools::EitherOrBoth::Left(value) => TagChange::Removed {
+                        key: key.clone(),
+                        value: value.to_owned(),
+                    },
+                    itertools::EitherOrBoth::Right(value) => TagChange::Added {
+                        key: key.clone(),
+                        value: value.to_owned(),
+                    },
+                });
+            }
+        }
+        changes
+    }
+    /// replaces the ID header of the opus stream `from` with `new_head` and writes the whole stream to `to`
+    ///
+    /// everything after the first page is copied verbatim, as is the first page, when `new_head` doesn't change it
+    ///
+    /// # Errors
+    /// when `from` doesn't start with a valid ID header or reading/writing fails
+    pub fn rewrite_head(
+        new_head: &OpusHead,
+        mut from: impl Read,
+        mut to: impl Write,
+    ) -> Result<(), error::Error> {
+        let mut head_ogg = OggPage::read_next_from(&mut from)?;
+        // validate current data
+        let head = OpusHead::from(&head_ogg)?;
+
+        let new_bytes = new_head.to_bytes();
+        // an unchanged head keeps the original page as is
+        if new_bytes != head.to_bytes() {
+            // only the uninterpreted bytes of undefined mapping families can exceed a page
+            head_ogg
+                .set_segment_table(lace(&new_bytes))
+                .map_err(|err| {
+                    Error::MalformedData(format!("OpusHead doesn't fit into a page: {err}"))
+                })?;
+        }
+        head_ogg.write_to(&mut to)?;
+
+        std::io::copy(&mut from, &mut to)?;
+        Ok(())
+    }
+    /// adds `db` to the output gain in the ID header of the opus file at `path`, clamped to the range of [`Gain`].
+    ///
+    /// only the first page is rewritten in place, the tags and audio data are left untouched
+    ///
+    /// # Errors
+    /// when the file doesn't start with a valid ID header or reading/writing fails
+    pub fn apply_output_gain_db(path: impl AsRef<Path>, db: f32) -> Result<(), error::Error> {
+        let mut file = std::fs::File::options().read(true).write(true).open(path)?;
+        let head_ogg = OggPage::read_next_from(&mut file)?;
+        let head = OpusHead::from(&head_ogg)?;
+        let gain = Gain::from_db(head.gain.as_db() + f64::from(db));
+
+        let mut page = vec![0; head_ogg.byte_len()];
+        file.rewind()?;
+        file.read_exact(&mut page)?;
+        // the ID header starts the payload and has a fixed layout, so only the gain bytes change
+        let gain_offset = 27 + page[26] as usize + 16;
+        page[gain_offset] = gain.m.to_le_bytes()[0];
+        page[gain_offset + 1] = gain.n;
+        OggPage::update_checksum(&mut page);
+        file.rewind()?;
+        file.write_all(&page)?;
+        Ok(())
+    }
+    /// the exact number of decodable samples per channel in `data`, at the 48 kHz granule rate of opus.
+    ///
+    /// the count is always based on 48 kHz, independent of the original sample rate in the head.
+    /// only the end of `data` is read to find the last page, see [`last_page`].
+    /// the granule position of the last page already excludes the padding at the end of the last packet,
+    /// so this is the final granule position minus the pre-skip, clamped at zero.
+    /// the stream is assumed to start at granule position zero
+    ///
+    /// # Errors
+    /// when `data` doesn't start with valid headers or has no end of stream page
+    pub fn sample_count(mut data: impl Read + Seek) -> Result<u64, error::Error> {
+        let (meta, pages) = Self::read_with_pages(&mut data)?;
+        let serial = pages[0].bitstream_serial_number;
+        let last = last_page(data, serial)?;
+        Ok(last
+            .granule_position
+            .saturating_sub(u64::from(meta.head.pre_skip)))
+    }
+    /// the playback time of `data`, see [`Self::sample_count`]
+    ///
+    /// # Errors
+    /// see [`Self::sample_count`]
+    pub fn duration(data: impl Read + Seek) -> Result<Duration, error::Error> {
+        Self::sample_count(data).map(samples_to_duration)
+    }
+    /// changes the pre-skip of the head, use [`Self::rewrite_pre_skip`] to change it in a stream
+    pub const fn set_pre_skip(&mut self, new: u16) {
+        self.head.pre_skip = new;
+    }
+    /// replaces the pre-skip of the opus stream `from` with `new` and writes the whole stream to `to`.
+    ///
+    /// the granule positions of all audio pages are shifted by the difference, so every page keeps its playback time.
+    /// all pages are rewritten one at a time
+    ///
+    /// # Errors
+    /// when `from` doesn't start with a valid ID header, reading/writing fails
+    /// or a granule position would become negative
+    pub fn rewrite_pre_skip(
+        new: u16,
+        from: impl Read,
+        mut to: impl Write,
+    ) -> Result<(), error::Error> {
+        let mut pages = OggPage::iterate_read(from);
+        let mut head_ogg = pages.next().ok_or(Error::NoMoreData)??;
+        let mut head = OpusHead::from(&head_ogg)?;
+        let delta = i64::from(new) - i64::from(head.pre_skip);
+        head.pre_skip = new;
+        head_ogg
+            .set_segment_table(lace(&head.to_bytes()))
+            .map_err(|err| {
+                Error::MalformedData(format!("OpusHead doesn't fit into a page: {err}"))
+            })?;
+        head_ogg.write_to(&mut to)?;
+
+        // the pages of the comment header keep their granule position
+        let mut in_comment_header = true;
+        for page in pages {
+            let mut page = page?;
+            if in_comment_header {
+                in_comment_header = !page
+                    .segment_table()
+                    .iter()
+                    .any(|segment| segment.len() < u8::MAX as usize);
+            } else if page.has_granule() {
+                page.granule_position = page
+                    .granule_position
+                    .checked_add_signed(delta)
+                    .filter(|&it| it != NO_GRANULE_POSITION)
+                    .ok_or_else(|| {
+                        Error::MalformedData(format!(
+                            "granule position {} of page {} can't be shifted by {delta}",
+                            page.granule_position, page.page_sequence_number
+                        ))
+                    })?;
+            }
+            page.write_to(&mut to)?;
+        }
+        Ok(())
+    }
+    /// scans all pages of the file at `path` to compute a [`StreamStats`]
+    ///
+    /// # Errors
+    /// when the file can't be read or contains malformed pages
+    pub fn stats(path: impl AsRef<Path>) -> Result<StreamStats, error::Error> {
+        let mut data = CountingRead::new(std::io::BufReader::new(std::fs::File::open(path)?));
+        let mut page_count = 0;
+        let mut last_granule = 0;
+        let mut pages = OggPage::iterate_read(&mut data).inspect(|page| {
+            if let Ok(page) = page {
+                page_count += 1;
+                // pages without a finished packet have no granule position
+                if page.has_granule() {
+                    last_granule = page.granule_position;
+                }
+            }
+        });
+        let (meta, _) = Self::read_from_pages(&mut pages, ParseOptions::default())?;
+        for page in pages {
+            page?;
+        }
+
+        let sample_count = last_granule.saturating_sub(meta.head.pre_skip as u64);
+        let duration = samples_to_duration(sample_count);
+        let average_bitrate = if duration.is_zero() {
+            0
+        } else {
+            ((data.count * 8) as f64 / duration.as_secs_f64()) as u64
+        };
+        Ok(StreamStats {
+            duration,
+            sample_count,
+            average_bitrate,
+            page_count,
+        })
+    }
+    /// reads every packet of the file at `path` to compute its [`GaplessInfo`].
+    ///
+    /// the end trim is the difference between the samples, that all audio packets decode to, and the final granule position
+    ///
+    /// # Errors
+    /// when the file can't be read, contains malformed pages or an audio packet has an invalid TOC byte
+    pub fn gapless_info(path: impl AsRef<Path>) -> Result<GaplessInfo, error::Error> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut packets = PacketKind::label(OggPage::iterate_packets(file));
+        let (_, head_packet) = packets.next().ok_or(Error::NoMoreData)??;
+        let head = OpusHead::from_packet(&head_packet.data, head_packet.granule_position)?;
+        let mut decoded_samples = 0;
+        let mut last_granule = 0;
+        for packet in packets {
+            let (kind, packet) = packet?;
+            if kind != PacketKind::Audio {
+                continue;
+            }
+            decoded_samples += packet_samples(&packet.data).ok_or_else(|| {
+                Error::MalformedData("audio packet without a valid TOC byte".to_owned())
+            })?;
+            last_granule = packet.granule_position;
+        }
+        Ok(GaplessInfo {
+            pre_skip: head.pre_skip,
+            end_trim: decoded_samples.saturating_sub(last_granule),
+            total_samples: last_granule.saturating_sub(u64::from(head.pre_skip)),
+        })
+    }
+    /// returns the byte offset of the first page, whose granule position lies after `target`.
+    ///
+    /// the granule position of opus always counts samples at 48 kHz, independent of the [`SampleRate`],
+    /// and is offset by the pre-skip of the [`OpusHead`].
+    /// The pages are searched by bisection, so only a few pages need to be read.
+    /// When `target` is after the end of the stream, its length is returned.
+    ///
+    /// # Errors
+    /// when `data` doesn't contain a valid opus stream or reading fails
+    pub fn seek_to<R: Read + Seek>(mut data: R, target: Duration) -> Result<u64, error::Error> {
+        /// below this distance the remaining pages are read linearly
+        const LINEAR_LIMIT: u64 = 4096;
+
+        data.rewind()?;
+        let (meta, header_len) = Self::read_from_counting(&mut data)?;
+        let target = OggPage::granule_from_duration(target, meta.head.pre_skip);
+        let end = data.seek(std::io::SeekFrom::End(0))?;
+
+        // the searched page starts in lo..=hi and lo is always the start of a page
+        let (mut lo, mut hi) = (header_len, end);
+        while hi - lo > LINEAR_LIMIT {
+            let mid = lo + (hi - lo) / 2;
+            match next_granule_page(&mut data, mid)? {
+                Some((start, _)) if start >= hi => hi = mid,
+                Some((start, page)) if page.granule_position > target => hi = start,
+                Some((start, page)) => lo = start + page.byte_len() as u64,
+                None => hi = mid,
+            }
+        }
+
+        data.seek(std::io::SeekFrom::Start(lo))?;
+        let mut offset = lo;
+        for page in OggPage::iterate_read(&mut data) {
+            let page = page?;
+            if page.has_granule() && page.granule_position > target {
+                return Ok(offset);
+            }
+            offset += page.byte_len() as u64;
+        }
+        Ok(end)
+    }
+    /// checks that the logical stream in `data` ends with an end of stream page for its serial.
+    ///
+    /// returns `false` for truncated streams, including those that end in the middle of a page
+    ///
+    /// # Errors
+    /// when reading fails or `data` contains malformed pages
+    pub fn check_complete(data: impl Read) -> Result<bool, error::Error> {
+        let mut serial = None;
+        for page in OggPage::iterate_read(data) {
+            let page = match page {
+                Ok(page) => page,
+                Err(Error::UnexpectedEoF) => return Ok(false),
+                Err(err) => return Err(err),
+            };
+            let serial = *serial.get_or_insert(page.bitstream_serial_number);
+            if page.bitstream_serial_number == serial && page.is_eos() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    /// reads the headers of the file at `path` once and collects the values of all `keys`.
+    ///
+    /// keys are matched case-insensitively, missing keys map to an empty [`Vec`]
+    ///
+    /// # Errors
+    /// see [`Self::read_from_file`]
+    pub fn read_tags(
+        path: impl AsRef<Path>,
+        keys: &[&str],
+    ) -> Result<std::collections::BTreeMap<String, Vec<String>>, error::Error> {
+        let tags = Self::read_from_file(path)?.tags;
+        Ok(keys
+            .iter()
+            .map(|&key| {
+                let values = tags
+                    .find_comments(key)
+                    .map(|it| it.value.clone())
+                    .collect_vec();
+                (key.to_owned(), values)
+            })
+            .collect())
+    }
+    /// the length in bytes of the comment header packet in `data`, without parsing the comments.
+    ///
+    /// only the segment tables of the header pages are read
+    ///
+    /// # Errors
+    /// when reading a page fails or `data` ends before the comment header does
+    pub fn comment_header_len(data: impl Read) -> Result<usize, Error> {
+        let mut packet_index = 0;
+        let mut len = 0;
+        for page in OggPage::iterate_read(data) {
+            for segment in page?.segment_table() {
+                if packet_index == 1 {
+                    len += segment.len();
+                }
+                if segment.len() < u8::MAX as usize {
+                    packet_index += 1;
+                    if packet_index == 2 {
+                        return Ok(len);
+                    }
+                }
+            }
+        }
+        Err(if packet_index == 0 {
+            Error::NoMoreData
+        } else {
+            Error::UnexpectedEoF
+        })
+    }
+    /// reads only the vendor string of the comment header in the file at `path`, none of the comments are parsed
+    ///
+    /// # Errors
+    /// when reading the file fails or its second packet isn't a comment header
+    pub fn read_vendor(path: impl AsRef<Path>) -> Result<String, Error> {
+        let mut packets = OggPage::iterate_packets(std::fs::File::open(path)?);
+        packets.next().ok_or(Error::NoMoreData)??;
+        let tags_packet = packets.next().ok_or(Error::NoMoreData)??;
+        expect_comment_header(&tags_packet.data)?;
+        read_length_encode_str(&mut &tags_packet.data[TAGS_MAGIC_STR.len()..])
+    }
+    /// copies every page of `from` to `to` with a recomputed checksum.
+    ///
+    /// returns the number of pages, whose checksum was wrong
+    ///
+    /// # Errors
+    /// when a page is malformed apart from its checksum or reading/writing fails
+    pub fn repair_crcs(mut from: impl Read, mut to: impl Write) -> Result<usize, Error> {
+        let mut repaired = 0;
+        let mut scratch = Vec::new();
+        loop {
+            let page = match OggPage::read_next_checked(&mut from, &mut scratch) {
+                Ok((page, has_valid_checksum)) => {
+                    repaired += usize::from(!has_valid_checksum);
+                    page
+                }
+                Err(Error::NoMoreData) => return Ok(repaired),
+                Err(err) => return Err(err),
+            };
+            page.write_to(&mut to)?;
+        }
+    }
+    /// writes the comment header of the file at `from` to the file at `to` with [`VorbisComment::write_opus_file`].
+    ///
+    /// the head and audio data of `to` are left untouched
+    ///
+    /// # Errors
+    /// see [`Self::read_from_file`] and [`VorbisComment::write_opus_file`]
+    pub fn copy_tags(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), Error> {
+        Self::read_from_file(from)?.tags.write_opus_file(to)
+    }
+    /// checks the page structure of every logical stream in `data`:
+    /// it starts with a begin of stream page, has contiguous sequence numbers from zero and ends with exactly one end of stream page
+    ///
+    /// # Errors
+    /// when reading fails or `data` contains malformed pages,
+    /// a violated invariant is reported as [`Error::MalformedData`] naming the offending page
+    pub fn validate_structure(data: impl Read) -> Result<(), error::Error> {
+        // the next expected sequence number of each stream, `None` after its end, in the order the streams started
+        let mut streams: Vec<(u32, Option<u32>)> = Vec::new();
+        for page in OggPage::iterate_read(data) {
+            let page = page?;
+            let (serial, seq) = (page.bitstream_serial_number, page.page_sequence_number);
+            let position = streams.iter().position(|&(it, _)| it == serial);
+            let expected = match position.map(|i| &streams[i].1) {
+                None => {
+                    require!(
+                        page.header_type.is_bos(),
+                        Error::MalformedData(format!(
+                            "page {seq} of stream {serial} starts the stream, but isn't flagged as begin of stream"
+                        ))
+                    );
+                    0
+                }
+                Some(Some(expected)) => {
+                    require!(
+                        !page.header_type.is_bos(),
+                        Error::MalformedData(format!(
+                            "page {seq} of stream {serial} is flagged as begin of stream, but isn't the first page"
+                        ))
+                    );
+                    *expected
+                }
+                Some(None) => {
+                    return Err(Error::MalformedData(format!(
+                        "page {seq} of stream {serial} follows the end of stream"
+                    )))
+                }
+            };
+            require!(
+                seq == expected,
+                Error::MalformedData(format!(
+                    "page {seq} of stream {serial} should have sequence number {expected}"
+                ))
+            );
+            let next = (!page.is_eos()).then_some(seq + 1);
+            match position {
+                Some(i) => streams[i].1 = next,
+                None => streams.push((serial, next)),
+            }
+        }
+        if let Some((serial, Some(next))) = streams.iter().find(|(_, next)| next.is_some()) {
+            return Err(Error::MalformedData(format!(
+                "page {} of stream {serial} is its last page, but isn't flagged as end of stream",
+                next - 1
+            )));
+        }
+        Ok(())
+    }
+    /// reads `Self` from the file at `path`, which may be compressed with gzip or xz.
+    ///
+    /// compressed files are detected by their magic bytes and need the `flate2` or `xz` feature respectively
+    ///
+    /// # Errors
+    /// when the file is compressed without the matching feature being enabled
+    /// when [`Self::read_from_file`] errors
+    pub fn read_from_path(path: impl AsRef<Path>) -> Result<Self, error::Error> {
+        use std::io::BufRead;
+
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let magic = file.fill_buf()?;
+        if magic.starts_with(GZIP_MAGIC_STR) {
+            #[cfg(feature = "flate2")]
+            return Self::read_from(flate2::bufread::GzDecoder::new(file));
+            #[cfg(not(feature = "flate2"))]
+            return Err(Error::UnsupportedCompression {
+                format: "gzip",
+                feature: "flate2",
+            });
+        }
+        if magic.starts_with(XZ_MAGIC_STR) {
+            #[cfg(feature = "xz")]
+            return Self::read_from(xz2::bufread::XzDecoder::new(file));
+            #[cfg(not(feature = "xz"))]
+            return Err(Error::UnsupportedCompression {
+                format: "xz",
+                feature: "xz",
+            });
+        }
+        Self::read_from(file)
     }
     /// reads `Self` from `path`
     ///
@@ -334,6 +2182,173 @@ impl OpusMeta {
         let file = std::fs::File::open(path)?;
         Self::read_from(file)
     }
+    /// checks whether the file at `path` starts with an ogg page containing an `OpusHead` packet.
+    ///
+    /// only the start of the first page is read and neither its checksum nor the head are validated
+    ///
+    /// # Errors
+    /// when opening or reading the file fails
+    pub fn is_opus(path: impl AsRef<Path>) -> Result<bool, error::Error> {
+        const HEADER_LEN: usize = 27;
+        let mut buf = Vec::new();
+        std::fs::File::open(path)?
+            .take((HEADER_LEN + u8::MAX as usize + HEAD_MAGIC_STR.len()) as u64)
+            .read_to_end(&mut buf)?;
+        if !buf.starts_with(crate::ogg::MAGIC_STR) || buf.len() < HEADER_LEN {
+            return Ok(false);
+        }
+        let payload_start = HEADER_LEN + buf[HEADER_LEN - 1] as usize;
+        Ok(buf
+            .get(payload_start..)
+            .is_some_and(|payload| payload.starts_with(HEAD_MAGIC_STR)))
+    }
+}
+
+/// writes a complete opus stream from its headers and audio packets
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpusWriter {
+    head: OpusHead,
+    tags: VorbisComment,
+    bitstream_serial_number: u32,
+}
+impl OpusWriter {
+    pub const fn new(head: OpusHead, tags: VorbisComment, bitstream_serial_number: u32) -> Self {
+        Self {
+            head,
+            tags,
+            bitstream_serial_number,
+        }
+    }
+    /// writes the ID header page, the comment header pages and then `audio_packets` to `to`.
+    ///
+    /// each audio packet is paired with the granule position at its end.
+    /// the packets are packed into pages of at most 255 segments, the last page is flagged as end of stream
+    ///
+    /// # Errors
+    /// when a header is too long to be encoded or writing fails
+    pub fn write(
+        &self,
+        audio_packets: impl IntoIterator<Item = (Vec<u8>, u64)>,
+        to: impl Write,
+    ) -> Result<(), Error> {
+        let mut stream = StreamWriter::new(to, self.bitstream_serial_number);
+        let page = |granule_position, segments, content: &str| {
+            OggPage::new(HeaderType::SIMPLE, granule_position, 0, 0, segments).map_err(|err| {
+                Error::MalformedData(format!("{content} doesn't fit into a page: {err}"))
+            })
+        };
+
+        stream.push(page(0, lace(&self.head.to_bytes()), "OpusHead")?)?;
+        let tag_segments = lace(&self.tags.to_bytes(TAGS_MAGIC_STR)?);
+        let segments_per_page = WriteOptions::default().segments_per_page();
+        let page_count = tag_segments.len().div_ceil(segments_per_page);
+        for (i, chunk) in tag_segments.chunks(segments_per_page).enumerate() {
+            let granule_position = if i + 1 == page_count {
+                0
+            } else {
+                NO_GRANULE_POSITION
+            };
+            stream.push(page(granule_position, chunk.to_vec(), "OpusTags")?)?;
+        }
+
+        // the audio starts on a new page
+        let mut segments = Vec::new();
+        let mut granule_position = NO_GRANULE_POSITION;
+        for (packet, packet_granule) in audio_packets {
+            for segment in lace(&packet) {
+                if segments.len() == u8::MAX as usize {
+                    stream.push(page(
+                        granule_position,
+                        std::mem::take(&mut segments),
+                        "audio",
+                    )?)?;
+                    // a page, on which no packet ends, has no granule position
+                    granule_position = NO_GRANULE_POSITION;
+                }
+                segments.push(segment);
+            }
+            granule_position = packet_granule;
+        }
+        if !segments.is_empty() {
+            stream.push(page(granule_position, segments, "audio")?)?;
+        }
+        stream.finish()?;
+        Ok(())
+    }
+}
+
+/// the number of samples at 48 kHz, that the opus `packet` decodes to, read from its TOC byte.
+///
+/// [spec](https://www.rfc-editor.org/rfc/rfc6716#section-3.1)
+fn packet_samples(packet: &[u8]) -> Option<u64> {
+    let toc = *packet.first()?;
+    let config = toc >> 3;
+    // in units of 2.5 ms, which are 120 samples
+    let frame_len = match config {
+        0..=11 => [4u8, 8, 16, 24][config as usize % 4],
+        12..=15 => [4, 8][config as usize % 2],
+        _ => [1, 2, 4, 8][config as usize % 4],
+    };
+    let frame_count = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1)? & 0x3F,
+    };
+    Some(u64::from(frame_len) * u64::from(frame_count) * 120)
+}
+/// finds the first valid page starting at or after `from`, that has a granule position
+fn next_granule_page(
+    data: &mut (impl Read + Seek),
+    from: u64,
+) -> Result<Option<(u64, OggPage)>, error::Error> {
+    const CHUNK: usize = 4096;
+    let mut chunk_start = from;
+    let mut buf = vec![0; CHUNK];
+    loop {
+        data.seek(std::io::SeekFrom::Start(chunk_start))?;
+        let mut len = 0;
+        while len < CHUNK {
+            match data.read(&mut buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if len < crate::ogg::MAGIC_STR.len() {
+            return Ok(None);
+        }
+        for i in 0..=len - crate::ogg::MAGIC_STR.len() {
+            if !buf[i..].starts_with(crate::ogg::MAGIC_STR) {
+                continue;
+            }
+            let mut start = chunk_start + i as u64;
+            data.seek(std::io::SeekFrom::Start(start))?;
+            // a false match of the magic string fails to read
+            let mut page = match OggPage::read_next_from(data) {
+                Ok(page) => page,
+                Err(
+                    Error::MalformedData(_) | Error::WrongChecksum { .. } | Error::UnexpectedEoF,
+                ) => continue,
+                Err(err) => return Err(err),
+            };
+            // pages without a finished packet have no granule position, so skip to the next one
+            loop {
+                if page.has_granule() {
+                    return Ok(Some((start, page)));
+                }
+                start += page.byte_len() as u64;
+                match OggPage::read_next_from(data) {
+                    Ok(next) => page = next,
+                    Err(Error::NoMoreData) => return Ok(None),
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        if len < CHUNK {
+            return Ok(None);
+        }
+        // overlap, so a magic string on the border is found
+        chunk_start += (len - crate::ogg::MAGIC_STR.len() + 1) as u64;
+    }
 }
 
 fn read_u32(read: &mut impl Read) -> Result<u32, error::Error> {
@@ -343,33 +2358,164 @@ fn read_u32(read: &mut impl Read) -> Result<u32, error::Error> {
 }
 fn read_length_encode_str(read: &mut impl Read) -> Result<String, error::Error> {
     let length = read_u32(read)?;
+    read_str(read, length, false)
+}
+/// escapes backslashes and line breaks like `vorbiscomment -e`
+fn escape_line(line: &str) -> String {
+    let mut escaped = String::with_capacity(line.len());
+    for c in line.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+/// reverts [`escape_line`]
+fn unescape_line(line: &str) -> Result<String, error::Error> {
+    let mut unescaped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next() {
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            escape => {
+                return Err(error::Error::MalformedData(format!(
+                    "invalid escape sequence {escape:?} in {line:?}"
+                )))
+            }
+        });
+    }
+    Ok(unescaped)
+}
+fn read_str(read: &mut impl Read, length: u32, lossy_utf8: bool) -> Result<String, error::Error> {
     let mut buf = vec![0; length as usize];
 
     read.read_exact(&mut buf)?;
-    Ok(String::from_utf8(buf)?)
+    if lossy_utf8 {
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    } else {
+        Ok(String::from_utf8(buf)?)
+    }
 }
 fn write_length_encode_str(write: &mut impl Write, s: &str) -> Result<(), error::Error> {
-    let len: u32 = s.len().try_into().expect("string to long");
-    write.write_all(&len.to_le_bytes())?;
+    write.write_all(&length_prefix(s.len())?)?;
     write.write_all(s.as_bytes())?;
     Ok(())
 }
+/// the little endian length prefix of a value with `len` bytes
+fn length_prefix(len: usize) -> Result<[u8; 4], error::Error> {
+    u32::try_from(len)
+        .map(u32::to_le_bytes)
+        .map_err(|_| Error::ValueTooLong { len })
+}
+
+/// conversions from and to the generic tags of [`lofty`], mapping its [`ItemKey`](lofty::ItemKey)s to vorbis keys
+#[cfg(feature = "lofty")]
+mod lofty_compat {
+    use super::VorbisComment;
+    use lofty::{ItemKey, ItemValue, Tag, TagItem, TagType};
+
+    /// only text items are converted, keys without a vorbis name are kept as they are
+    impl From<Tag> for VorbisComment {
+        fn from(tag: Tag) -> Self {
+            let mut comments = Self::default();
+            for item in tag.items() {
+                if let (Some(key), Some(value)) = (
+                    item.key().map_key(TagType::VorbisComments, true),
+                    item.value().text(),
+                ) {
+                    comments.add_comment((key, value));
+                }
+            }
+            comments
+        }
+    }
+    impl From<&VorbisComment> for Tag {
+        fn from(comments: &VorbisComment) -> Self {
+            let mut tag = Self::new(TagType::VorbisComments);
+            for comment in &comments.comments {
+                tag.push(TagItem::new(
+                    ItemKey::from_key(TagType::VorbisComments, &comment.key),
+                    ItemValue::Text(comment.value.clone()),
+                ));
+            }
+            tag
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::opus_tagger::DEFAULT_VENDOR;
+
+        #[test]
+        fn lofty_round_trip() {
+            let tags = VorbisComment::new(
+                DEFAULT_VENDOR,
+                vec![
+                    ("TITLE", "titel"),
+                    ("ARTIST", "jemand"),
+                    ("ALBUM", "album"),
+                    ("TRACKNUMBER", "3"),
+                    ("X-UNKNOWN", "bleibt"),
+                ],
+            );
+
+            let tag = Tag::from(&tags);
+            assert_eq!(Some("titel"), tag.get_string(&ItemKey::TrackTitle));
+            assert_eq!(Some("jemand"), tag.get_string(&ItemKey::TrackArtist));
+            assert_eq!(Some("album"), tag.get_string(&ItemKey::AlbumTitle));
+            assert_eq!(Some("3"), tag.get_string(&ItemKey::TrackNumber));
+
+            assert_eq!(tags, VorbisComment::from(tag));
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// a path in the temp dir, that is unique to this test run, so concurrent runs don't collide
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("opus_tag_{}_{name}", std::process::id()))
+    }
+    /// the header pages of `res/tag_test.opus`, after `modify` changed its head and comment page
+    fn rebuilt_header_pages(modify: impl FnOnce(&mut OggPage, &mut OggPage)) -> Vec<u8> {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut pages = OggPage::iterate_read(data.as_slice());
+        let mut head_ogg = pages.next().unwrap().unwrap();
+        let mut tags_ogg = pages.next().unwrap().unwrap();
+        modify(&mut head_ogg, &mut tags_ogg);
+        let mut buf = Vec::new();
+        head_ogg.write_to(&mut buf).unwrap();
+        tags_ogg.write_to(&mut buf).unwrap();
+        buf
+    }
+
     #[test]
     fn test_read_opus() {
         assert_eq!(
             OpusMeta {
                 head: OpusHead {
+                    granule_position: 0,
                     version: 1,
                     channel_count: 2,
                     pre_skip: 312,
-                    sample_rate: SampleRate::KHz48,
+                    sample_rate: 48000,
                     gain: Gain { m: 0, n: 0 },
                     channel_map: MappingFamily::RTP,
+                    mapping_table: None,
                 },
                 tags: VorbisComment::new(
                     "Lavf60.3.100",
@@ -401,12 +2547,14 @@ mod tests {
         assert_eq!(
             OpusMeta {
                 head: OpusHead {
+                    granule_position: 0,
                     version: 1,
                     channel_count: 2,
                     pre_skip: 312,
-                    sample_rate: SampleRate::KHz48,
+                    sample_rate: 48000,
                     gain: Gain { m: 0, n: 0 },
                     channel_map: MappingFamily::RTP,
+                    mapping_table: None,
                 },
                 tags: VorbisComment::new(
                     "Lavf60.3.100",
@@ -451,7 +2599,7 @@ mod tests {
 
         let mut new_buf = Vec::new();
         new_tags
-            .update_opus_tags(buf.as_slice(), &mut new_buf)
+            .update_opus_tags(buf.as_slice(), &mut new_buf, WriteOptions::default())
             .unwrap();
 
         let mut new_oggs = OggPage::iterate_read(new_buf.as_slice());
@@ -462,9 +2610,11 @@ mod tests {
             "first Packet failed"
         );
         let _ = original_oggs.next().unwrap().unwrap();
+        let tags_ogg = new_oggs.next().unwrap().unwrap();
+        assert_eq!(tags_ogg.granule_position, 0, "granule needs to be zero");
         assert_eq!(
             new_tags,
-            VorbisComment::from(&new_oggs.next().unwrap().unwrap(), TAGS_MAGIC_STR).unwrap(),
+            VorbisComment::from_bytes(&tags_ogg.payload(), TAGS_MAGIC_STR).unwrap(),
             "second Packet failed"
         );
         assert_eq!(
@@ -473,4 +2623,1973 @@ mod tests {
             "third Packet failed"
         );
     }
+
+    #[test]
+    fn head_round_trip() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let head_ogg = OggPage::read_next_from(&mut data.as_slice()).unwrap();
+        assert_eq!(
+            head_ogg.payload(),
+            OpusHead::from(&head_ogg).unwrap().to_bytes()
+        );
+    }
+    #[test]
+    fn head_round_trip_multichannel() {
+        let mut bytes = b"OpusHead".to_vec();
+        bytes.extend([1, 6, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 1]);
+        bytes.extend([4, 2, 0, 4, 1, 2, 3, 5]);
+
+        let head = OpusHead::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            Some(ChannelMappingTable {
+                stream_count: 4,
+                coupled_count: 2,
+                channel_mapping: vec![0, 4, 1, 2, 3, 5],
+                extra: Vec::new(),
+            }),
+            head.mapping_table
+        );
+        assert_eq!(bytes, head.to_bytes());
+    }
+    #[test]
+    fn multistream_counts() {
+        let mut bytes = b"OpusHead".to_vec();
+        bytes.extend([1, 6, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 1]);
+        bytes.extend([4, 2, 0, 4, 1, 2, 3, 5]);
+        let head = OpusHead::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            (Some(4), Some(2)),
+            (head.stream_count(), head.coupled_count())
+        );
+
+        let head = OpusMeta::read_from_file("./res/tag_test.opus")
+            .unwrap()
+            .head;
+        assert_eq!((None, None), (head.stream_count(), head.coupled_count()));
+    }
+    #[test]
+    fn head_round_trip_undefined_family() {
+        let mut bytes = b"OpusHead".to_vec();
+        bytes.extend([1, 2, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 255]);
+        bytes.extend([1, 1, 0, 1]);
+        bytes.extend(b"unknown");
+
+        let head = OpusHead::from_bytes(&bytes).unwrap();
+        assert_eq!(MappingFamily::NotDefined(255), head.channel_map);
+        assert_eq!(bytes, head.to_bytes());
+
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut buf = Vec::new();
+        OpusMeta::rewrite_head(&head, data.as_slice(), &mut buf).unwrap();
+        assert_eq!(head, OpusMeta::read_from(buf.as_slice()).unwrap().head);
+
+        // defined families still need the exact length
+        bytes[18] = 1;
+        assert!(OpusHead::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn read_unusually_laced_headers() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut pages = OggPage::iterate_read(data.as_slice());
+        let head = pages.next().unwrap().unwrap().payload();
+        let tags = VorbisComment::new("vendor", vec![("TITLE", "long".repeat(75))]);
+        let tags_packet = tags.to_bytes(TAGS_MAGIC_STR).unwrap();
+        assert_eq!(332, tags_packet.len(), "needs to span two segments");
+        let mut tag_segments = tags_packet.chunks(255).map(<[u8]>::to_vec);
+
+        // both headers start on the first page, the comment header continues on the second
+        let mut buf = Vec::new();
+        OggPage::new(
+            crate::ogg::HeaderType::BOS,
+            0,
+            1,
+            0,
+            vec![head, tag_segments.next().unwrap()],
+        )
+        .unwrap()
+        .write_to(&mut buf)
+        .unwrap();
+        OggPage::new(
+            crate::ogg::HeaderType::CONTINUATION,
+            0,
+            1,
+            1,
+            tag_segments.collect_vec(),
+        )
+        .unwrap()
+        .write_to(&mut buf)
+        .unwrap();
+
+        let meta = OpusMeta::read_from(buf.as_slice()).unwrap();
+        assert_eq!(
+            OpusMeta::read_from(data.as_slice()).unwrap().head,
+            meta.head
+        );
+        assert_eq!(tags, meta.tags);
+    }
+
+    #[test]
+    fn channel_layout() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut head = OpusMeta::read_from(data.as_slice()).unwrap().head;
+        assert_eq!(ChannelLayout::Stereo, head.layout());
+
+        head.channel_count = 1;
+        assert_eq!(ChannelLayout::Mono, head.layout());
+
+        let mut bytes = b"OpusHead".to_vec();
+        bytes.extend([1, 6, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 1]);
+        bytes.extend([4, 2, 0, 4, 1, 2, 3, 5]);
+        assert_eq!(
+            ChannelLayout::FiveOne,
+            OpusHead::from_bytes(&bytes).unwrap().layout()
+        );
+    }
+
+    #[test]
+    fn channel_order() {
+        let mut bytes = b"OpusHead".to_vec();
+        bytes.extend([1, 6, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 1]);
+        bytes.extend([4, 2, 0, 4, 1, 2, 3, 5]);
+        let mut head = OpusHead::from_bytes(&bytes).unwrap();
+        // front left, front right, center, lfe, rear left, rear right
+        assert_eq!(Some(vec![0, 2, 1, 5, 3, 4]), head.decode_channel_order());
+
+        head.channel_count = 2;
+        assert_eq!(Some(vec![0, 1]), head.decode_channel_order());
+        head.channel_count = 9;
+        assert_eq!(None, head.decode_channel_order());
+    }
+
+    #[test]
+    fn sample_rate_round_trip() {
+        for rate in SampleRate::ALL {
+            assert_eq!(Ok(rate), SampleRate::try_from(u32::from(rate)));
+            assert_eq!(rate.as_hz().to_string(), rate.to_string());
+        }
+        assert_eq!(Some(SampleRate::KHz48), SampleRate::known(48000));
+        assert_eq!(None, SampleRate::known(44100));
+        assert_eq!("48000", SampleRate::KHz48.to_string());
+
+        let mut bytes = OpusMeta::read_from_file("./res/tag_test.opus")
+            .unwrap()
+            .head
+            .to_bytes();
+        bytes[12..16].copy_from_slice(&44100u32.to_le_bytes());
+        let head = OpusHead::from_bytes(&bytes).unwrap();
+        assert_eq!(44100, head.sample_rate());
+        assert_eq!(None, SampleRate::known(head.sample_rate()));
+        assert_eq!(bytes, head.to_bytes());
+    }
+
+    #[test]
+    fn tolerate_nonzero_head_granule() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut head_ogg = OggPage::read_next_from(&mut data.as_slice()).unwrap();
+        head_ogg.granule_position = 5;
+
+        let head = OpusHead::from(&head_ogg).unwrap();
+        assert_eq!(5, head.granule_position());
+        assert!(!head.has_zero_granule());
+    }
+
+    #[test]
+    fn write_multiple_files() {
+        let paths = (0..2)
+            .map(|i| temp_path(&format!("write_multiple_{i}.opus")))
+            .collect_vec();
+        for path in &paths {
+            std::fs::copy("./res/tag_test.opus", path).unwrap();
+        }
+        let missing = temp_path("write_multiple_missing.opus");
+
+        let new_tags = VorbisComment::new("something new", vec![("ALBUM", "Gruselkabinett")]);
+        let results =
+            new_tags.write_opus_files(paths.iter().cloned().chain(std::iter::once(missing)));
+
+        assert_eq!(3, results.len());
+        for (path, result) in &results[..2] {
+            assert!(result.is_ok(), "failed to write {path:?}: {result:?}");
+            assert_eq!(new_tags, OpusMeta::read_from_file(path).unwrap().tags);
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(results[2].1.is_err(), "missing file should fail");
+    }
+
+    #[test]
+    fn preview_equals_written() {
+        let path = temp_path("preview_equals_written.opus");
+        std::fs::copy("./res/tag_test.opus", &path).unwrap();
+
+        let new_tags = VorbisComment::new("something new", vec![("TITLE", "vorschau")]);
+        let preview = new_tags
+            .preview_update(std::fs::File::open(&path).unwrap())
+            .unwrap();
+        new_tags.write_opus_file(&path).unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), preview);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn query_keys() {
+        let tags = VorbisComment::new(
+            "vendor",
+            vec![("ARTIST", "a"), ("artist", "b"), ("TITLE", "c")],
+        );
+        assert!(tags.contains_key("Artist"));
+        assert!(tags.contains_key("title"));
+        assert!(!tags.contains_key("ALBUM"));
+        assert_eq!(2, tags.count("ARTIST"));
+        assert_eq!(1, tags.count("TITLE"));
+        assert_eq!(0, tags.count("ALBUM"));
+    }
+
+    #[test]
+    fn parse_comment() {
+        assert_eq!(
+            Comment::from(("A", "B=C")),
+            Comment::parse("A=B=C").unwrap()
+        );
+        assert_eq!(Comment::from(("A", "")), Comment::parse("A=").unwrap());
+        assert!(matches!(
+            Comment::parse("TITLE"),
+            Err(Error::MalformedData(_))
+        ));
+        assert_eq!(
+            "comment \"TITLE\" has no '=' between key and value, an empty value needs to be written as \"TITLE=\"",
+            Comment::parse("TITLE").unwrap_err().to_string()
+        );
+
+        let mut packet = VorbisComment::new("", vec![("COMMENT", "")])
+            .to_bytes(TAGS_MAGIC_STR)
+            .unwrap();
+        let tags = VorbisComment::from_bytes(&packet, TAGS_MAGIC_STR).unwrap();
+        assert_eq!(Some(""), tags.get_first("COMMENT"));
+        // drop the separator of the only comment
+        packet[16] -= 1;
+        packet.pop();
+        let err = VorbisComment::from_bytes(&packet, TAGS_MAGIC_STR).unwrap_err();
+        assert!(
+            matches!(&err, Error::MalformedComment { index: 0, offset: 16, length: 7, source } if matches!(**source, Error::MalformedData(_))),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let tags = VorbisComment::new(
+            DEFAULT_VENDOR,
+            vec![("LYRICS", "erste Zeile\nzweite \\ Zeile"), ("TITLE", "a=b")],
+        );
+        let lines = tags.to_lines();
+        assert_eq!(
+            vec!["LYRICS=erste Zeile\\nzweite \\\\ Zeile", "TITLE=a=b"],
+            lines
+        );
+        assert_eq!(tags, VorbisComment::from_lines(&lines).unwrap());
+        assert!(VorbisComment::from_lines(["TITLE=\\x"]).is_err());
+        assert!(VorbisComment::from_lines(["TITLE=\\"]).is_err());
+    }
+
+    #[test]
+    fn distinct_keys() {
+        let tags = VorbisComment::new(
+            "vendor",
+            vec![
+                ("artist", "a"),
+                ("TITLE", "b"),
+                ("ARTIST", "c"),
+                ("Title", "d"),
+            ],
+        );
+        assert_eq!(vec!["artist", "TITLE"], tags.keys().collect_vec());
+        assert_eq!(0, VorbisComment::empty("vendor").keys().count());
+    }
+
+    #[test]
+    fn find_duplicate_keys() {
+        let tags = VorbisComment::new(
+            "vendor",
+            vec![
+                ("artist", "a"),
+                ("TITLE", "b"),
+                ("ARTIST", "c"),
+                ("Artist", "d"),
+            ],
+        );
+        assert_eq!(vec![("artist".to_owned(), 3)], tags.duplicate_keys());
+        assert!(VorbisComment::new("vendor", vec![("TITLE", "b")])
+            .duplicate_keys()
+            .is_empty());
+    }
+
+    #[test]
+    fn cleanup_comments() {
+        let mut tags = VorbisComment::new(
+            "vendor",
+            vec![
+                ("ARTIST", "Bram Stoker "),
+                ("title", " \tDas Amulett der Mumie"),
+            ],
+        );
+        tags.trim_values();
+        assert_eq!(
+            VorbisComment::new(
+                "vendor",
+                vec![
+                    ("ARTIST", "Bram Stoker"),
+                    ("title", "Das Amulett der Mumie")
+                ]
+            ),
+            tags
+        );
+        tags.normalize_keys(KeyCase::Upper);
+        assert_eq!(
+            vec!["ARTIST", "TITLE"],
+            tags.comments.iter().map(|it| it.key.as_str()).collect_vec()
+        );
+        tags.normalize_keys(KeyCase::Lower);
+        assert_eq!(
+            vec!["artist", "title"],
+            tags.comments.iter().map(|it| it.key.as_str()).collect_vec()
+        );
+    }
+
+    #[test]
+    fn read_counting() {
+        // the comment header of the bundled file ends at this offset
+        const END_PACKET_2: u64 = 0x118;
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let (meta, count) = OpusMeta::read_from_counting(data.as_slice()).unwrap();
+        assert_eq!(END_PACKET_2, count);
+        assert_eq!(OpusMeta::read_from(data.as_slice()).unwrap(), meta);
+        OggPage::read_next_from(&mut &data[count as usize..]).expect("should be at a page start");
+    }
+
+    #[test]
+    fn detect_truncation() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        assert!(OpusMeta::check_complete(data.as_slice()).unwrap());
+        assert!(!OpusMeta::check_complete(&data[..data.len() / 2]).unwrap());
+
+        // truncated exactly at a page boundary
+        let (_, header_len) = OpusMeta::read_from_counting(data.as_slice()).unwrap();
+        assert!(!OpusMeta::check_complete(&data[..header_len as usize]).unwrap());
+    }
+
+    #[test]
+    fn stripped_tags() {
+        let tags = VorbisComment::stripped("vendor");
+        assert_eq!("vendor", tags.vendor);
+        assert!(tags.comments.is_empty());
+        assert_eq!(
+            tags,
+            VorbisComment::from_bytes(&tags.to_bytes(TAGS_MAGIC_STR).unwrap(), TAGS_MAGIC_STR)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn strip_tags() {
+        let path = temp_path("strip_tags.opus");
+        std::fs::copy("./res/tag_test.opus", &path).unwrap();
+
+        VorbisComment::strip_tags_file(&path).unwrap();
+
+        let tags = OpusMeta::read_from_file(&path).unwrap().tags;
+        assert_eq!(VorbisComment::stripped(""), tags);
+        assert_eq!(0, tags.comments.len());
+        assert_eq!(
+            OpusMeta::stats("./res/tag_test.opus").unwrap().sample_count,
+            OpusMeta::stats(&path).unwrap().sample_count
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_with_tmp_dir() {
+        let dir = temp_path("write_with_tmp_dir");
+        let (target_dir, tmp_dir) = (dir.join("target"), dir.join("tmp"));
+        std::fs::create_dir_all(&target_dir).unwrap();
+        std::fs::create_dir_all(&tmp_dir).unwrap();
+        let path = target_dir.join("file.opus");
+        std::fs::copy("./res/tag_test.opus", &path).unwrap();
+
+        let new_tags = VorbisComment::new("something new", vec![("TITLE", "woanders")]);
+        new_tags.write_opus_file_with_tmp(&path, &tmp_dir).unwrap();
+
+        assert_eq!(new_tags, OpusMeta::read_from_file(&path).unwrap().tags);
+        assert_eq!(0, std::fs::read_dir(&tmp_dir).unwrap().count());
+        assert_eq!(1, std::fs::read_dir(&target_dir).unwrap().count());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clone_and_modify() {
+        let original = VorbisComment::new("vendor", vec![("TITLE", "original")]);
+        let mut modified = original.clone();
+        modified.add_comment(("ARTIST", "someone"));
+
+        assert_eq!(1, original.comments.len());
+        assert_eq!(2, modified.comments.len());
+        assert_ne!(original, modified);
+    }
+
+    #[test]
+    fn raw_binary_values() {
+        let picture = "AAAAAwAAAAlpbWFnZS9wbmc=";
+        let mut tags = VorbisComment::new("vendor", vec![("TITLE", "bild")]);
+        tags.add_raw("METADATA_BLOCK_PICTURE", picture);
+
+        let tags =
+            VorbisComment::from_bytes(&tags.to_bytes(TAGS_MAGIC_STR).unwrap(), TAGS_MAGIC_STR)
+                .unwrap();
+        assert_eq!(
+            vec![picture],
+            tags.raw_values("metadata_block_picture").collect_vec()
+        );
+        assert_eq!(0, tags.raw_values("COVERART").count());
+    }
+
+    #[test]
+    fn edit_comments_in_place() {
+        let mut tags = VorbisComment::new("vendor", vec![("TITLE", " a "), ("TITLE", "b")]);
+        for comment in tags.comments_mut() {
+            comment.value = format!("Titel: {}", comment.value.trim());
+        }
+        assert_eq!(
+            VorbisComment::new("vendor", vec![("TITLE", "Titel: a"), ("TITLE", "Titel: b")]),
+            tags
+        );
+    }
+
+    #[test]
+    fn default_comment() {
+        let tags = VorbisComment::default();
+        assert_eq!(DEFAULT_VENDOR, tags.vendor);
+        assert!(DEFAULT_VENDOR.starts_with("opus_tag "));
+        assert!(tags.comments.is_empty());
+    }
+
+    #[test]
+    fn index_by_key() {
+        let tags = VorbisComment::new("vendor", vec![("TITLE", "first"), ("title", "second")]);
+        assert_eq!("first", &tags["Title"]);
+        assert_eq!(Some("first"), tags.get_first("TITLE"));
+        assert_eq!(None, tags.get_first("ARTIST"));
+    }
+    #[test]
+    #[should_panic(expected = "no comment with key \"ARTIST\"")]
+    fn index_by_missing_key() {
+        let tags = VorbisComment::new("vendor", vec![("TITLE", "first")]);
+        let _ = &tags["ARTIST"];
+    }
+
+    #[test]
+    fn summary() {
+        let mut meta = OpusMeta::read_from_file("./res/tag_test.opus").unwrap();
+        meta.tags = VorbisComment::new("vendor", vec![("TITLE", "Zusammenfassung")]);
+        meta.head.gain = Gain { m: -0x80, n: 0x01 };
+
+        let summary = meta.to_string();
+        assert!(summary.contains("Encoded with vendor\n"), "{summary}");
+        assert!(summary.contains("\tTITLE=Zusammenfassung\n"), "{summary}");
+        assert!(summary.contains("Playback gain: 1.5 dB\n"), "{summary}");
+        assert!(
+            summary.contains(&format!("Channels: {}\n", meta.head.channel_count)),
+            "{summary}"
+        );
+    }
+
+    #[test]
+    fn read_compressed() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let expected = OpusMeta::read_from(data.as_slice()).unwrap();
+        let path = temp_path("read_compressed.opus");
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(expected, OpusMeta::read_from_path(&path).unwrap());
+
+        #[cfg(feature = "flate2")]
+        {
+            use std::io::Write;
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&data).unwrap();
+            std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+            assert_eq!(expected, OpusMeta::read_from_path(&path).unwrap());
+        }
+        #[cfg(feature = "xz")]
+        {
+            use std::io::Write;
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+            encoder.write_all(&data).unwrap();
+            std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+            assert_eq!(expected, OpusMeta::read_from_path(&path).unwrap());
+        }
+        #[cfg(not(feature = "xz"))]
+        {
+            std::fs::write(&path, XZ_MAGIC_STR).unwrap();
+            assert!(matches!(
+                OpusMeta::read_from_path(&path),
+                Err(Error::UnsupportedCompression { feature: "xz", .. })
+            ));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_from_memory() {
+        let data = include_bytes!("../res/tag_test.opus");
+        assert_eq!(
+            OpusMeta::read_from_file("./res/tag_test.opus").unwrap(),
+            OpusMeta::read_from_bytes(data).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_ignoring_crc_errors() {
+        let mut data = std::fs::read("./res/tag_test.opus").unwrap();
+        // corrupt the last byte of the comment header, which is ASCII and stays valid UTF-8
+        let (_, header_len) = OpusMeta::read_from_counting(data.as_slice()).unwrap();
+        data[header_len as usize - 1] ^= 0x20;
+
+        assert!(matches!(
+            OpusMeta::read_from(data.as_slice()),
+            Err(Error::WrongChecksum { .. })
+        ));
+        let options = ParseOptions {
+            ignore_crc_errors: true,
+            ..Default::default()
+        };
+        OpusMeta::read_from_with(data.as_slice(), options).unwrap();
+    }
+    #[test]
+    fn parse_with_strict_granule() {
+        let buf = rebuilt_header_pages(|head_ogg, _| head_ogg.granule_position = 5);
+
+        OpusMeta::read_from(buf.as_slice()).unwrap();
+        let options = ParseOptions {
+            allow_nonzero_head_granule: false,
+            ..Default::default()
+        };
+        assert!(OpusMeta::read_from_with(buf.as_slice(), options).is_err());
+    }
+    #[test]
+    fn parse_with_max_comment_bytes() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let options = |max| ParseOptions {
+            max_comment_bytes: Some(max),
+            ..Default::default()
+        };
+        OpusMeta::read_from_with(data.as_slice(), options(0xCD)).unwrap();
+        assert!(OpusMeta::read_from_with(data.as_slice(), options(0xCC)).is_err());
+    }
+
+    #[test]
+    fn read_header_pages() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let (meta, pages) = OpusMeta::read_with_pages(data.as_slice()).unwrap();
+        assert_eq!(OpusMeta::read_from(data.as_slice()).unwrap(), meta);
+
+        let first = OggPage::read_next_from(&mut data.as_slice()).unwrap();
+        assert_eq!(2, pages.len());
+        assert_eq!(first, pages[0]);
+        assert!(pages
+            .iter()
+            .all(|it| it.bitstream_serial_number == first.bitstream_serial_number));
+        assert_eq!(
+            vec![0, 1],
+            pages.iter().map(|it| it.page_sequence_number).collect_vec()
+        );
+    }
+
+    #[test]
+    fn extend_comments() {
+        let mut tags = VorbisComment::new("vendor", vec![("TITLE", "first")]);
+        tags.extend([("A", "1"), ("B", "2")]);
+        tags.append_all(vec![Comment::from(("C", "3"))]);
+        assert_eq!(
+            VorbisComment::new(
+                "vendor",
+                vec![("TITLE", "first"), ("A", "1"), ("B", "2"), ("C", "3")]
+            ),
+            tags
+        );
+    }
+
+    #[test]
+    fn remove_by_predicate() {
+        let mut tags = VorbisComment::new(
+            "vendor",
+            vec![
+                ("TITLE", "title"),
+                ("ARTIST", ""),
+                ("ALBUM", "album"),
+                ("GENRE", ""),
+            ],
+        );
+        tags.remove_empty_values();
+        assert_eq!(
+            VorbisComment::new("vendor", vec![("TITLE", "title"), ("ALBUM", "album")]),
+            tags
+        );
+        tags.retain(|it| it.key != "ALBUM");
+        assert_eq!(VorbisComment::new("vendor", vec![("TITLE", "title")]), tags);
+    }
+
+    #[test]
+    fn rewrite_head_keeps_tags() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let original = OpusMeta::read_from(data.as_slice()).unwrap();
+
+        let mut new_head = original.head.clone();
+        new_head.gain = Gain { m: 3, n: 0x80 };
+        let mut buf = Vec::new();
+        OpusMeta::rewrite_head(&new_head, data.as_slice(), &mut buf).unwrap();
+        let rewritten = OpusMeta::read_from(buf.as_slice()).unwrap();
+        assert_eq!(new_head, rewritten.head);
+        assert_eq!(original.tags, rewritten.tags);
+        assert_eq!(data.len(), buf.len());
+
+        // a head with a mapping table is longer than the original
+        new_head.channel_map = MappingFamily::VorbisChannelOrder;
+        new_head.mapping_table = Some(ChannelMappingTable {
+            stream_count: 1,
+            coupled_count: 1,
+            channel_mapping: vec![0, 1],
+            extra: Vec::new(),
+        });
+        let mut buf = Vec::new();
+        OpusMeta::rewrite_head(&new_head, data.as_slice(), &mut buf).unwrap();
+        let rewritten = OpusMeta::read_from(buf.as_slice()).unwrap();
+        assert_eq!(new_head, rewritten.head);
+        assert_eq!(original.tags, rewritten.tags);
+        assert_eq!(data.len() + 4, buf.len());
+    }
+
+    #[test]
+    fn report_invalid_comment() {
+        let mut packet = VorbisComment::new("vendor", vec![("TITLE", "ok"), ("ARTIST", "bad")])
+            .to_bytes(TAGS_MAGIC_STR)
+            .unwrap();
+        let len = packet.len();
+        packet[len - 1] = 0xFF; // invalid UTF-8
+
+        let err = VorbisComment::from_bytes(&packet, TAGS_MAGIC_STR).unwrap_err();
+        match err {
+            Error::MalformedComment {
+                index,
+                offset,
+                length,
+                source,
+            } => {
+                assert_eq!(1, index);
+                // magic, vendor, number of comments and the first comment
+                assert_eq!(8 + 4 + 6 + 4 + 4 + 8, offset);
+                assert_eq!(10, length);
+                assert!(matches!(*source, Error::FromUtf8(_)), "got {source:?}");
+            }
+            err => panic!("expected MalformedComment, got {err:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_lossy_utf8() {
+        let mut packet = VorbisComment::new("vendor", vec![("TITLE", "Cafe")])
+            .to_bytes(TAGS_MAGIC_STR)
+            .unwrap();
+        *packet.last_mut().unwrap() = 0xE9; // latin-1 for '\u{e9}'
+        let buf = rebuilt_header_pages(|_, tags_ogg| {
+            tags_ogg.set_segment_table(lace(&packet)).unwrap();
+        });
+
+        assert!(OpusMeta::read_from(buf.as_slice()).is_err());
+        let options = ParseOptions {
+            lossy_utf8: true,
+            ..Default::default()
+        };
+        let meta = OpusMeta::read_from_with(buf.as_slice(), options).unwrap();
+        assert_eq!(Some("Caf\u{fffd}"), meta.tags.get_first("TITLE"));
+    }
+
+    #[test]
+    fn stream_stats() {
+        let stats = OpusMeta::stats("./res/tag_test.opus").unwrap();
+        assert_eq!(10, stats.page_count);
+        // last granule position minus pre-skip
+        assert_eq!(351_707 - 312, stats.sample_count);
+        assert_eq!(7, stats.duration.as_secs());
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        assert_eq!(
+            351_707 - 312,
+            OpusMeta::sample_count(std::io::Cursor::new(&data)).unwrap()
+        );
+        assert_eq!(
+            stats.duration,
+            OpusMeta::duration(std::io::Cursor::new(&data)).unwrap()
+        );
+        assert!(
+            (30_000..40_000).contains(&stats.average_bitrate),
+            "implausible bitrate {}",
+            stats.average_bitrate
+        );
+    }
+
+    #[test]
+    fn vorbis_round_trip() {
+        let tags = VorbisComment::new("Xiph.Org libVorbis", vec![("TITLE", "vorbis")]);
+        let bytes = tags.to_vorbis_bytes().unwrap();
+        assert!(bytes.starts_with(b"\x03vorbis"));
+        assert_eq!(Some(&0x01), bytes.last());
+        assert_eq!(tags, VorbisComment::from_vorbis_bytes(&bytes).unwrap());
+
+        assert!(VorbisComment::from_vorbis_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(VorbisComment::from_vorbis_bytes(&tags.to_bytes(TAGS_MAGIC_STR).unwrap()).is_err());
+    }
+
+    #[test]
+    fn report_eof_kind() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let head_page_len = 0x2F;
+        for (data, is_page_boundary) in [
+            (&data[..0], true),
+            (&data[..head_page_len], true),
+            (&data[..head_page_len + 10], false),
+            (&data[..head_page_len - 1], false),
+        ] {
+            let err = OpusMeta::read_from(data).unwrap_err();
+            if is_page_boundary {
+                assert!(matches!(err, Error::NoMoreData), "got {err:?}");
+            } else {
+                assert!(matches!(err, Error::UnexpectedEoF), "got {err:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn detect_encoder() {
+        assert_eq!(
+            EncoderInfo {
+                library: EncoderLibrary::Libavformat,
+                version: Some("60.3.100".to_owned()),
+                encoder: None,
+                encoded_by: None,
+            },
+            VorbisComment::empty("Lavf60.3.100").encoder_info()
+        );
+        assert_eq!(
+            EncoderInfo {
+                library: EncoderLibrary::Libopus,
+                version: Some("1.3.1".to_owned()),
+                encoder: Some("opusenc from opus-tools 0.2".to_owned()),
+                encoded_by: Some("someone".to_owned()),
+            },
+            VorbisComment::new(
+                "libopus 1.3.1",
+                vec![
+                    ("ENCODER", "opusenc from opus-tools 0.2"),
+                    ("ENCODED-BY", "someone")
+                ]
+            )
+            .encoder_info()
+        );
+        assert_eq!(
+            EncoderLibrary::Other("something else".to_owned()),
+            VorbisComment::empty("something else")
+                .encoder_info()
+                .library
+        );
+    }
+
+    #[test]
+    fn update_equal_length_in_place() {
+        let path = temp_path("update_equal_length_in_place.opus");
+        std::fs::copy("./res/tag_test.opus", &path).unwrap();
+        let original_len = std::fs::metadata(&path).unwrap().len();
+        let mut file = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        let mut tags = OpusMeta::read_from_file(&path).unwrap().tags;
+        tags.add_comment(("EXTRA", "longer"));
+        assert!(!tags.update_in_place(&mut file).unwrap());
+        assert_ne!(tags, OpusMeta::read_from_file(&path).unwrap().tags);
+
+        tags.remove_first("EXTRA");
+        tags.vendor = tags.vendor.to_ascii_uppercase();
+        assert!(tags.update_in_place(&mut file).unwrap());
+        drop(file);
+        assert_eq!(tags, OpusMeta::read_from_file(&path).unwrap().tags);
+        assert_eq!(original_len, std::fs::metadata(&path).unwrap().len());
+        assert!(OpusMeta::check_complete(std::fs::File::open(&path).unwrap()).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replace_read_only_file() {
+        let path = temp_path("replace_read_only_file.opus");
+        std::fs::copy("./res/tag_test.opus", &path).unwrap();
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+
+        let mut tags = OpusMeta::read_from_file(&path).unwrap().tags;
+        // an equal length edit, that would be written in place
+        tags.vendor = tags.vendor.to_ascii_uppercase();
+        tags.write_opus_file(&path).unwrap();
+        assert_eq!(tags, OpusMeta::read_from_file(&path).unwrap().tags);
+
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        tags.add_comment(("EXTRA", "longer"));
+        tags.write_opus_file(&path).unwrap();
+        assert_eq!(tags, OpusMeta::read_from_file(&path).unwrap().tags);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seek_to_time() {
+        let mut file = std::fs::File::open("./res/tag_test.opus").unwrap();
+        let stats = OpusMeta::stats("./res/tag_test.opus").unwrap();
+        let pre_skip = OpusMeta::read_from(&mut file).unwrap().head.pre_skip;
+
+        let middle = OpusMeta::seek_to(&mut file, stats.duration / 2).unwrap();
+        file.seek(std::io::SeekFrom::Start(middle)).unwrap();
+        let page = OggPage::read_next_from(&mut file).unwrap();
+        assert!(page.granule_duration(pre_skip).unwrap() > stats.duration / 2);
+
+        let mut last = 0;
+        for i in 0..=10 {
+            let offset = OpusMeta::seek_to(&mut file, stats.duration * i / 10).unwrap();
+            assert!(last <= offset, "seeking needs to be monotonic");
+            last = offset;
+        }
+        assert_eq!(
+            std::fs::metadata("./res/tag_test.opus").unwrap().len(),
+            OpusMeta::seek_to(&mut file, stats.duration * 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn split_comment_pages() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let original = OpusMeta::read_from(data.as_slice()).unwrap();
+        let mut new_tags = original.tags.clone();
+        new_tags.add_comment(("LYRICS", "la".repeat(500).as_str()));
+        let options = WriteOptions {
+            max_page_payload: 2 * 255 + 100,
+            ..Default::default()
+        };
+
+        let split = new_tags
+            .preview_update_with(data.as_slice(), options)
+            .unwrap();
+        let pages = OggPage::iterate_read(split.as_slice())
+            .map(Result::unwrap)
+            .collect_vec();
+        let comment_len = new_tags.to_bytes(TAGS_MAGIC_STR).unwrap().len();
+        let comment_pages = comment_len.div_ceil(2 * 255);
+        assert!(comment_pages > 2, "test needs several comment pages");
+        for page in &pages[1..=comment_pages] {
+            assert!(page.payload_len() <= options.max_page_payload);
+        }
+        for (i, page) in pages.iter().enumerate() {
+            assert_eq!(
+                i as u32, page.page_sequence_number,
+                "pages need to be renumbered"
+            );
+        }
+        assert_eq!(
+            new_tags,
+            OpusMeta::read_from(split.as_slice()).unwrap().tags
+        );
+
+        // joining the pages again restores the original stream
+        let joined = original.tags.preview_update(split.as_slice()).unwrap();
+        assert_eq!(data, joined);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn reject_too_long_value() {
+        assert_eq!([3, 0, 0, 0], length_prefix(3).unwrap());
+        assert_eq!([0xFF; 4], length_prefix(u32::MAX as usize).unwrap());
+        let len = u32::MAX as usize + 1;
+        assert!(matches!(
+            length_prefix(len),
+            Err(Error::ValueTooLong { len: got }) if got == len
+        ));
+    }
+
+    #[test]
+    fn label_packets() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let kinds = PacketKind::label(OggPage::iterate_packets(data.as_slice()))
+            .map_ok(|(kind, _)| kind)
+            .take(3)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                PacketKind::IdHeader,
+                PacketKind::CommentHeader,
+                PacketKind::Audio
+            ],
+            kinds
+        );
+        assert_eq!(PacketKind::Audio, PacketKind::classify(2, TAGS_MAGIC_STR));
+    }
+
+    #[test]
+    fn unchanged_head_keeps_page() {
+        let mut data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut rest = data.as_slice();
+        let mut head_ogg = OggPage::read_next_from(&mut rest).unwrap();
+        let mut head_packet = head_ogg.payload();
+        head_packet[8] = 2; // a minor version, that needs to be kept when serialized
+        head_ogg.set_segment_table(lace(&head_packet)).unwrap();
+        let mut buf = Vec::new();
+        head_ogg.write_to(&mut buf).unwrap();
+        buf.extend(rest);
+        data = buf;
+
+        let meta = OpusMeta::read_from(data.as_slice()).unwrap();
+        let mut buf = Vec::new();
+        OpusMeta::rewrite_head(&meta.head, data.as_slice(), &mut buf).unwrap();
+        assert_eq!(data, buf, "unchanged head needs to keep the page");
+        assert_eq!(data, meta.tags.preview_update(data.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn read_several_tags() {
+        let path = temp_path("read_several_tags.opus");
+        std::fs::copy("./res/tag_test.opus", &path).unwrap();
+        VorbisComment::new(
+            "vendor",
+            vec![("TITLE", "titel"), ("artist", "a"), ("ARTIST", "b")],
+        )
+        .write_opus_file(&path)
+        .unwrap();
+
+        let tags = OpusMeta::read_tags(&path, &["TITLE", "ARTIST", "ALBUM"]).unwrap();
+        assert_eq!(
+            std::collections::BTreeMap::from([
+                ("TITLE".to_owned(), vec!["titel".to_owned()]),
+                ("ARTIST".to_owned(), vec!["a".to_owned(), "b".to_owned()]),
+                ("ALBUM".to_owned(), vec![]),
+            ]),
+            tags
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn validate_page_structure() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        OpusMeta::validate_structure(data.as_slice()).unwrap();
+        assert!(OpusMeta::validate_structure(&data[..data.len() / 2]).is_err());
+
+        let write_pages = |pages: &[(HeaderType, u32)]| {
+            let mut buf = Vec::new();
+            for &(header_type, seq) in pages {
+                OggPage::from_payload(header_type, 0, 7, seq, b"data")
+                    .unwrap()
+                    .write_to(&mut buf)
+                    .unwrap();
+            }
+            buf
+        };
+        let assert_malformed =
+            |pages: &[(HeaderType, u32)], expected: &str| match OpusMeta::validate_structure(
+                write_pages(pages).as_slice(),
+            ) {
+                Err(Error::MalformedData(msg)) => assert!(msg.starts_with(expected), "{msg}"),
+                result => panic!("expected MalformedData, got {result:?}"),
+            };
+
+        OpusMeta::validate_structure(
+            write_pages(&[(HeaderType::BOS, 0), (HeaderType::EOS, 1)]).as_slice(),
+        )
+        .unwrap();
+        assert_malformed(
+            &[(HeaderType::SIMPLE, 0), (HeaderType::EOS, 1)],
+            "page 0 of stream 7",
+        );
+        assert_malformed(
+            &[
+                (HeaderType::BOS, 0),
+                (HeaderType::SIMPLE, 2),
+                (HeaderType::EOS, 3),
+            ],
+            "page 2 of stream 7",
+        );
+        assert_malformed(
+            &[
+                (HeaderType::BOS, 0),
+                (HeaderType::EOS, 1),
+                (HeaderType::EOS, 2),
+            ],
+            "page 2 of stream 7",
+        );
+        assert_malformed(
+            &[(HeaderType::BOS, 0), (HeaderType::SIMPLE, 1)],
+            "page 1 of stream 7 is its last page",
+        );
+
+        // the first stream without an end is reported
+        let mut buf = Vec::new();
+        for (serial, header_type, seq) in [
+            (9, HeaderType::BOS, 0),
+            (3, HeaderType::BOS, 0),
+            (9, HeaderType::SIMPLE, 1),
+            (3, HeaderType::SIMPLE, 1),
+            (3, HeaderType::SIMPLE, 2),
+        ] {
+            OggPage::from_payload(header_type, 0, serial, seq, b"data")
+                .unwrap()
+                .write_to(&mut buf)
+                .unwrap();
+        }
+        match OpusMeta::validate_structure(buf.as_slice()) {
+            Err(Error::MalformedData(msg)) => {
+                assert!(msg.starts_with("page 1 of stream 9"), "{msg}");
+            }
+            result => panic!("expected MalformedData, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn skip_pages_without_granule() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut pages = OggPage::iterate_read(data.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // end the stream with a page, on which no packet ends
+        let last = pages.last_mut().unwrap();
+        last.set_flags(HeaderType::SIMPLE);
+        let continuation = OggPage::new(
+            HeaderType::CONTINUATION | HeaderType::EOS,
+            NO_GRANULE_POSITION,
+            last.bitstream_serial_number,
+            last.page_sequence_number + 1,
+            vec![vec![0; 255]],
+        )
+        .unwrap();
+        pages.push(continuation);
+        let mut buf = Vec::new();
+        for page in pages {
+            page.write_to(&mut buf).unwrap();
+        }
+        let path = temp_path("skip_pages_without_granule.opus");
+        std::fs::write(&path, &buf).unwrap();
+
+        let original = OpusMeta::stats("./res/tag_test.opus").unwrap();
+        let stats = OpusMeta::stats(&path).unwrap();
+        assert_eq!(original.sample_count, stats.sample_count);
+        assert_eq!(original.page_count + 1, stats.page_count);
+        assert_eq!(
+            buf.len() as u64,
+            OpusMeta::seek_to(std::io::Cursor::new(&buf), original.duration * 2).unwrap()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recount_comments() {
+        let tags = VorbisComment::new("vendor", vec![("TITLE", "a"), ("ARTIST", "b")]);
+        let mut packet = tags.to_bytes(TAGS_MAGIC_STR).unwrap();
+        // the number of comments follows the magic string and the vendor
+        packet[8 + 4 + 6] += 1;
+        let buf = rebuilt_header_pages(|_, tags_ogg| {
+            tags_ogg.set_segment_table(lace(&packet)).unwrap();
+        });
+
+        assert!(OpusMeta::read_from(buf.as_slice()).is_err());
+        let options = ParseOptions {
+            recount_comments: true,
+            ..Default::default()
+        };
+        let (meta, truncated) =
+            OpusMeta::read_from_with_truncation(buf.as_slice(), options).unwrap();
+        assert!(truncated);
+        assert_eq!(tags, meta.tags);
+
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let (_, truncated) = OpusMeta::read_from_with_truncation(data.as_slice(), options).unwrap();
+        assert!(!truncated);
+
+        // a corrupt count doesn't reserve memory for all declared comments
+        packet[8 + 4 + 6..8 + 4 + 6 + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let buf = rebuilt_header_pages(|_, tags_ogg| {
+            tags_ogg.set_segment_table(lace(&packet)).unwrap();
+        });
+        let (meta, truncated) =
+            OpusMeta::read_from_with_truncation(buf.as_slice(), options).unwrap();
+        assert!(truncated);
+        assert_eq!(tags, meta.tags);
+    }
+
+    #[test]
+    fn parse_any_comment_header() {
+        let tags = VorbisComment::new("vendor", vec![("TITLE", "beides")]);
+        let opus = OggPage::from_payload(
+            HeaderType::SIMPLE,
+            0,
+            1,
+            1,
+            &tags.to_bytes(TAGS_MAGIC_STR).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            (tags.clone(), MagicKind::Opus),
+            VorbisComment::from_any(&opus).unwrap()
+        );
+        let vorbis = OggPage::from_payload(
+            HeaderType::SIMPLE,
+            0,
+            1,
+            1,
+            &tags.to_vorbis_bytes().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            (tags, MagicKind::Vorbis),
+            VorbisComment::from_any(&vorbis).unwrap()
+        );
+        let other = OggPage::from_payload(HeaderType::SIMPLE, 0, 1, 1, b"OpusHead").unwrap();
+        assert!(VorbisComment::from_any(&other).is_err());
+    }
+
+    #[test]
+    fn shift_pre_skip() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut meta = OpusMeta::read_from(data.as_slice()).unwrap();
+        let mut buf = Vec::new();
+        OpusMeta::rewrite_pre_skip(412, data.as_slice(), &mut buf).unwrap();
+
+        meta.set_pre_skip(412);
+        assert_eq!(meta, OpusMeta::read_from(buf.as_slice()).unwrap());
+        OpusMeta::validate_structure(buf.as_slice()).unwrap();
+        let original_pages = OggPage::iterate_read(data.as_slice()).map(Result::unwrap);
+        let pages = OggPage::iterate_read(buf.as_slice()).map(Result::unwrap);
+        for (i, (original, page)) in original_pages.zip(pages).enumerate().skip(1) {
+            let expected = match i {
+                1 => original.granule_position,
+                _ => original.granule_position + 100,
+            };
+            assert_eq!(expected, page.granule_position, "page {i}");
+            assert_eq!(original.granule_duration(312), page.granule_duration(412));
+        }
+
+        let mut back = Vec::new();
+        OpusMeta::rewrite_pre_skip(312, buf.as_slice(), &mut back).unwrap();
+        assert_eq!(data, back);
+    }
+
+    #[test]
+    fn write_comments_at_lacing_boundary() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let tags = VorbisComment::new("vendor", vec![("TITLE", "x".repeat(478))]);
+        assert_eq!(510, tags.to_bytes(TAGS_MAGIC_STR).unwrap().len());
+
+        let mut buf = Vec::new();
+        tags.update_opus_tags(data.as_slice(), &mut buf, WriteOptions::default())
+            .unwrap();
+        let tags_ogg = OggPage::iterate_read(buf.as_slice())
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        let segment_lengths = tags_ogg.segment_table().iter().map(Vec::len).collect_vec();
+        assert_eq!(
+            vec![255, 255, 0],
+            segment_lengths,
+            "needs a terminating segment"
+        );
+        let audio_ogg = OggPage::iterate_read(buf.as_slice())
+            .nth(2)
+            .unwrap()
+            .unwrap();
+        assert!(!audio_ogg.header_type.is_continuation());
+
+        assert_eq!(tags, OpusMeta::read_from(buf.as_slice()).unwrap().tags);
+        OpusMeta::validate_structure(buf.as_slice()).unwrap();
+    }
+
+    #[test]
+    fn count_comment_pages() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let original_pages = OggPage::count_pages(data.as_slice()).unwrap();
+        let written_pages = |tags: &VorbisComment, options| {
+            let mut buf = Vec::new();
+            tags.update_opus_tags(data.as_slice(), &mut buf, options)
+                .unwrap();
+            OggPage::count_pages(buf.as_slice()).unwrap() + 1 - original_pages
+        };
+
+        let tiny = VorbisComment::new("vendor", vec![("TITLE", "tiny")]);
+        assert_eq!(1, tiny.page_count(WriteOptions::default().max_page_payload));
+        assert_eq!(1, tiny.page_count(0));
+        assert_eq!(1, written_pages(&tiny, WriteOptions::default()));
+
+        let big = VorbisComment::new("vendor", vec![("COVER", "x".repeat(70_000))]);
+        assert_eq!(2, big.page_count(WriteOptions::default().max_page_payload));
+        assert_eq!(2, written_pages(&big, WriteOptions::default()));
+        let options = WriteOptions {
+            max_page_payload: 4096,
+            ..Default::default()
+        };
+        assert_eq!(18, big.page_count(options.max_page_payload));
+        assert_eq!(18, written_pages(&big, options));
+
+        // exactly filling two segments needs a third, empty one
+        let boundary = VorbisComment::new("vendor", vec![("TITLE", "x".repeat(478))]);
+        assert_eq!(1, boundary.page_count(765));
+        assert_eq!(2, boundary.page_count(510));
+    }
+
+    #[test]
+    fn copy_tags() {
+        let from = temp_path("copy_tags_from.opus");
+        let to = temp_path("copy_tags_to.opus");
+        std::fs::copy("./res/tag_test.opus", &from).unwrap();
+        std::fs::copy("./res/tag_test.opus", &to).unwrap();
+        let tags = VorbisComment::new(
+            "encoder",
+            vec![("ARTIST", "b"), ("TITLE", "t"), ("ARTIST", "a")],
+        );
+        tags.write_opus_file(&from).unwrap();
+        let original = OpusMeta::read_from_file(&to).unwrap();
+
+        OpusMeta::copy_tags(&from, &to).unwrap();
+
+        let copied = OpusMeta::read_from_file(&to).unwrap();
+        assert_eq!(tags, copied.tags);
+        assert_eq!(original.head, copied.head);
+        assert_eq!(
+            OpusMeta::stats(&from).unwrap().sample_count,
+            OpusMeta::stats(&to).unwrap().sample_count
+        );
+        std::fs::remove_file(&from).unwrap();
+        std::fs::remove_file(&to).unwrap();
+    }
+
+    #[test]
+    fn parse_year() {
+        let mut tags = VorbisComment::new("", vec![("YEAR", "2004")]);
+        assert_eq!(Some(2004), tags.year());
+        tags.add_comment(("DATE", "1999-05-01"));
+        assert_eq!(Some(1999), tags.year(), "DATE takes precedence");
+        assert_eq!(
+            None,
+            VorbisComment::new("", vec![("DATE", "19990"), ("YEAR", "05/01")]).year()
+        );
+        assert_eq!(None, VorbisComment::empty("").year());
+
+        tags.add_comment(("DATE", "1998"));
+        tags.set_year(2010);
+        assert_eq!(Some(2010), tags.year());
+        assert_eq!("2004", &tags["YEAR"]);
+        assert_eq!(1, tags.count("DATE"));
+        assert_eq!("2010", &tags["DATE"]);
+        let mut tags = VorbisComment::empty("");
+        tags.set_year(2004);
+        assert_eq!(Some("2004"), tags.get_first("date"));
+    }
+
+    #[test]
+    fn parse_track_and_disc() {
+        let tags = VorbisComment::new("", vec![("TRACKNUMBER", "2"), ("TOTALTRACKS", "182")]);
+        assert_eq!(Some((2, Some(182))), tags.track());
+        let tags = VorbisComment::new("", vec![("TRACKNUMBER", "01"), ("TRACKTOTAL", "00")]);
+        assert_eq!(Some((1, None)), tags.track());
+        let tags = VorbisComment::new("", vec![("TRACKNUMBER", "2/182"), ("TRACKTOTAL", "5")]);
+        assert_eq!(Some((2, Some(182))), tags.track());
+        let tags = VorbisComment::new("", vec![("TRACKNUMBER", "2/"), ("TRACKTOTAL", "5")]);
+        assert_eq!(Some((2, Some(5))), tags.track());
+        let tags = VorbisComment::new("", vec![("TRACKNUMBER", "2/ "), ("TOTALTRACKS", "7")]);
+        assert_eq!(Some((2, Some(7))), tags.track());
+        let tags = VorbisComment::new("", vec![("DISCNUMBER", "1/2"), ("TRACKTOTAL", "5")]);
+        assert_eq!(Some((1, Some(2))), tags.disc());
+        assert_eq!(None, tags.track());
+        let tags = VorbisComment::new("", vec![("TRACKNUMBER", "A1")]);
+        assert_eq!(None, tags.track());
+
+        let mut tags =
+            VorbisComment::new("", vec![("TRACKNUMBER", "2/182"), ("TOTALTRACKS", "182")]);
+        tags.set_track(3, Some(12));
+        assert_eq!(Some((3, Some(12))), tags.track());
+        assert!(!tags.contains_key("TOTALTRACKS"));
+        tags.set_track(4, None);
+        assert_eq!(Some((4, None)), tags.track());
+        assert_eq!(1, tags.comments.len());
+    }
+
+    #[test]
+    fn sniff_opus() {
+        assert!(OpusMeta::is_opus("./res/tag_test.opus").unwrap());
+        assert!(!OpusMeta::is_opus("./res/id3test.mp3").unwrap());
+
+        let path = temp_path("sniff_opus.bin");
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        for blob in [&b"OggS"[..], b"", &data[..30]] {
+            std::fs::write(&path, blob).unwrap();
+            assert!(!OpusMeta::is_opus(&path).unwrap(), "{blob:?}");
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(OpusMeta::is_opus("./res/missing.opus").is_err());
+    }
+
+    #[test]
+    fn apply_output_gain() {
+        let path = temp_path("apply_output_gain.opus");
+        std::fs::copy("./res/tag_test.opus", &path).unwrap();
+        let original = OpusMeta::read_from_file(&path).unwrap();
+
+        OpusMeta::apply_output_gain_db(&path, 3.0).unwrap();
+
+        let meta = OpusMeta::read_from_file(&path).unwrap();
+        assert_eq!(
+            Gain::from_db(original.head.gain.as_db() + 3.0),
+            meta.head.gain
+        );
+        let data = std::fs::read(&path).unwrap();
+        let original_data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut rest = data.as_slice();
+        OggPage::read_next_from(&mut rest).unwrap();
+        assert_eq!(&original_data[data.len() - rest.len()..], rest);
+
+        OpusMeta::apply_output_gain_db(&path, 1000.0).unwrap();
+        assert_eq!(
+            Gain::from_db(f64::from(i16::MAX) / 256.0),
+            OpusMeta::read_from_file(&path).unwrap().head.gain
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(Gain { m: 0, n: 0 }, Gain::from_db(0.0));
+        assert_eq!(Gain { m: -0x80, n: 0x01 }, Gain::from_db(1.5));
+    }
+
+    #[test]
+    fn report_missing_comment_header() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut buf = Vec::new();
+        for (i, page) in OggPage::iterate_read(data.as_slice()).enumerate() {
+            if i != 1 {
+                page.unwrap().write_to(&mut buf).unwrap();
+            }
+        }
+        let is_unexpected_packet = |err: &Error| matches!(err, Error::UnexpectedPacket { expected: "OpusTags", found } if found.len() == 8 && !found.starts_with(b"OpusTags"));
+
+        let err = OpusMeta::read_from(buf.as_slice()).unwrap_err();
+        assert!(is_unexpected_packet(&err), "{err:?}");
+        let err = VorbisComment::empty("")
+            .update_opus_tags(buf.as_slice(), std::io::sink(), WriteOptions::default())
+            .unwrap_err();
+        assert!(is_unexpected_packet(&err), "{err:?}");
+    }
+
+    #[test]
+    fn write_synthetic_stream() {
+        let head = OpusMeta::read_from_file("./res/tag_test.opus")
+            .unwrap()
+            .head;
+        let tags = VorbisComment::new("opus_tag", vec![("TITLE", "synthetic")]);
+        let packets = vec![
+            (vec![1; 3], 960),
+            (vec![2; 600], 1920),
+            (vec![3; 255 * 600], 2880),
+            (vec![4; 255], 3840),
+        ];
+
+        let mut buf = Vec::new();
+        OpusWriter::new(head.clone(), tags.clone(), 42)
+            .write(packets.clone(), &mut buf)
+            .unwrap();
+
+        let meta = OpusMeta::read_from(buf.as_slice()).unwrap();
+        assert_eq!(OpusMeta { head, tags }, meta);
+        OpusMeta::validate_structure(buf.as_slice()).unwrap();
+        let read_packets = OggPage::iterate_packets(buf.as_slice())
+            .skip(2)
+            .map(|it| it.unwrap().data)
+            .collect_vec();
+        assert_eq!(
+            packets.iter().map(|(data, _)| data.clone()).collect_vec(),
+            read_packets
+        );
+        let granules = OggPage::iterate_read(buf.as_slice())
+            .map(|it| it.unwrap().granule_position)
+            .collect_vec();
+        assert_eq!(vec![0, 0, 1920, NO_GRANULE_POSITION, 3840], granules);
+        assert_eq!(
+            3840 - u64::from(meta.head.pre_skip),
+            OpusMeta::sample_count(std::io::Cursor::new(buf)).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_through_symlink() {
+        let dir = temp_path("write_through_symlink");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let target = dir.join("target.opus");
+        let link = dir.join("link.opus");
+        std::fs::copy("./res/tag_test.opus", &target).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let original = OpusMeta::read_from_file(&target).unwrap().tags;
+        // too long to be updated in place
+        let tags = VorbisComment::new("vendor", vec![("LYRICS", "la".repeat(500))]);
+        let options = |symlinks| WriteOptions {
+            symlinks,
+            ..Default::default()
+        };
+
+        let err = tags
+            .write_opus_file_with(&link, options(SymlinkBehavior::Reject))
+            .unwrap_err();
+        assert_eq!(crate::error::ErrorKind::Io, err.kind(), "{err:?}");
+        assert_eq!(original, OpusMeta::read_from_file(&target).unwrap().tags);
+
+        tags.write_opus_file_with(&link, options(SymlinkBehavior::Follow))
+            .unwrap();
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(tags, OpusMeta::read_from_file(&target).unwrap().tags);
+
+        original
+            .write_opus_file_with(&link, options(SymlinkBehavior::Replace))
+            .unwrap();
+        assert!(!link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(original, OpusMeta::read_from_file(&link).unwrap().tags);
+        assert_eq!(tags, OpusMeta::read_from_file(&target).unwrap().tags);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn measure_comment_header() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let tags = OpusMeta::read_from(data.as_slice()).unwrap().tags;
+        assert_eq!(
+            tags.to_bytes(TAGS_MAGIC_STR).unwrap().len(),
+            OpusMeta::comment_header_len(data.as_slice()).unwrap()
+        );
+
+        let tags = VorbisComment::new("vendor", vec![("LYRICS", "la".repeat(500))]);
+        let options = WriteOptions {
+            max_page_payload: 255,
+            ..Default::default()
+        };
+        let split = tags.preview_update_with(data.as_slice(), options).unwrap();
+        assert_eq!(
+            tags.to_bytes(TAGS_MAGIC_STR).unwrap().len(),
+            OpusMeta::comment_header_len(split.as_slice()).unwrap()
+        );
+
+        assert!(OpusMeta::comment_header_len(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn parse_version_nibbles() {
+        let mut bytes = OpusMeta::read_from_file("./res/tag_test.opus")
+            .unwrap()
+            .head
+            .to_bytes();
+        bytes[8] = 0x0F;
+        let head = OpusHead::from_bytes(&bytes).unwrap();
+        assert_eq!((0, 15), (head.major_version(), head.minor_version()));
+        assert_eq!(bytes, head.to_bytes());
+        assert_eq!(head, OpusHead::from_bytes(&head.to_bytes()).unwrap());
+
+        bytes[8] = 0x10;
+        let err = OpusHead::from_bytes(&bytes).unwrap_err();
+        assert!(matches!(err, Error::UnsupportetVersion(0x10)), "{err:?}");
+        assert_eq!(
+            "only supports major version 0, but got version 1.0",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn locate_comments() {
+        let tags = VorbisComment::new("v", vec![("A", "1"), ("BB", "22")]);
+        let payload = tags.to_bytes(TAGS_MAGIC_STR).unwrap();
+        let page = OggPage::from_payload(HeaderType::SIMPLE, 0, 0, 1, &payload).unwrap();
+
+        let (read, ranges) = VorbisComment::from_with_offsets(&page).unwrap();
+        assert_eq!(tags, read);
+        // magic string, vendor and the number of comments take 17 bytes
+        assert_eq!(vec![17..24, 24..33], ranges);
+        assert_eq!(b"\x05\0\0\0BB=22", &payload[ranges[1].clone()]);
+    }
+
+    #[test]
+    fn read_value_spanning_pages() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let picture =
+            "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/".repeat(300_000 / 64);
+        let mut tags = VorbisComment::new("vendor", vec![("TITLE", "picture")]);
+        tags.add_raw("METADATA_BLOCK_PICTURE", picture.as_str());
+        tags.add_comment(("ARTIST", "after the picture"));
+        assert_eq!(5, tags.page_count(WriteOptions::default().max_page_payload));
+
+        let mut buf = Vec::new();
+        tags.update_opus_tags(data.as_slice(), &mut buf, WriteOptions::default())
+            .unwrap();
+
+        let meta = OpusMeta::read_from(buf.as_slice()).unwrap();
+        assert_eq!(tags, meta.tags);
+        assert_eq!(
+            vec![picture.as_str()],
+            meta.tags.raw_values("METADATA_BLOCK_PICTURE").collect_vec()
+        );
+        // the multi-page header is read again, when it is replaced
+        let mut rewritten = Vec::new();
+        VorbisComment::empty("")
+            .update_opus_tags(buf.as_slice(), &mut rewritten, WriteOptions::default())
+            .unwrap();
+        assert_eq!(
+            VorbisComment::empty(""),
+            OpusMeta::read_from(rewritten.as_slice()).unwrap().tags
+        );
+        assert_eq!(
+            OggPage::count_pages(data.as_slice()).unwrap(),
+            OggPage::count_pages(rewritten.as_slice()).unwrap()
+        );
+    }
+
+    #[test]
+    fn audiobook_round_trip() {
+        let mut tags = OpusMeta::read_from_file("./res/tag_test.opus")
+            .unwrap()
+            .tags;
+        tags.append_all([
+            ("CHAPTER001", "00:22:37.040"),
+            ("CHAPTER001NAME", "Part 2"),
+            ("CHAPTER000", "00:00:00.000"),
+            ("CHAPTER000NAME", "Part 1"),
+            ("CHAPTER002", "01:00:15.6"),
+        ]);
+        let book = AudiobookMeta::from(&tags);
+        assert_eq!(
+            AudiobookMeta {
+                title: Some("title".to_owned()),
+                author: Some("artist".to_owned()),
+                narrator: None,
+                series: Some("album".to_owned()),
+                part: Some(5),
+                chapters: vec![
+                    Chapter {
+                        start: Duration::ZERO,
+                        name: Some("Part 1".to_owned())
+                    },
+                    Chapter {
+                        start: Duration::from_millis(22 * 60_000 + 37_040),
+                        name: Some("Part 2".to_owned())
+                    },
+                    Chapter {
+                        start: Duration::from_millis(3_615_600),
+                        name: None
+                    },
+                ],
+            },
+            book
+        );
+
+        let mut written = tags.clone();
+        book.write_to(&mut written);
+        assert_eq!(book, AudiobookMeta::from(&written));
+        assert_eq!(Some((5, Some(7))), written.track());
+        assert_eq!("00:22:37.040", &written["CHAPTER001"]);
+        assert_eq!("01:00:15.600", &written["CHAPTER002"]);
+
+        let mut empty = VorbisComment::empty("");
+        book.write_to(&mut empty);
+        assert_eq!(book, AudiobookMeta::from(&empty));
+        let narrated = VorbisComment::new("", vec![("ARTIST", "reader")]);
+        assert_eq!(
+            Some("reader"),
+            AudiobookMeta::from(&narrated).narrator.as_deref()
+        );
+    }
+
+    #[test]
+    fn canonicalize_aliased_keys() {
+        let mut tags = VorbisComment::new(
+            "",
+            vec![
+                ("date", "2004"),
+                ("Album Artist", "a"),
+                ("year", "2005"),
+                ("TotalTracks", "182"),
+                ("title", "t"),
+            ],
+        );
+        tags.canonicalize_keys();
+        assert_eq!(
+            vec!["DATE", "ALBUMARTIST", "DATE", "TRACKTOTAL", "TITLE"],
+            tags.comments.iter().map(|it| it.key.as_str()).collect_vec()
+        );
+        assert!(KEY_ALIASES
+            .iter()
+            .all(|(alias, canonical)| alias != canonical && *alias == alias.to_ascii_uppercase()));
+    }
+
+    #[test]
+    fn read_only_vendor() {
+        assert_eq!(
+            "Lavf60.3.100",
+            OpusMeta::read_vendor("./res/tag_test.opus").unwrap()
+        );
+        assert!(OpusMeta::read_vendor("./res/id3test.mp3").is_err());
+    }
+
+    #[test]
+    fn repair_wrong_checksums() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut corrupted = data.clone();
+        let mut offsets = Vec::new();
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            offsets.push(data.len() - rest.len());
+            OggPage::read_next_from(&mut rest).unwrap();
+        }
+        // the checksum is at byte 22 of each page
+        for &offset in offsets.iter().step_by(2) {
+            corrupted[offset + 22] ^= 0xFF;
+        }
+        assert!(OpusMeta::validate_structure(corrupted.as_slice()).is_err());
+
+        let mut repaired = Vec::new();
+        let count = OpusMeta::repair_crcs(corrupted.as_slice(), &mut repaired).unwrap();
+        assert_eq!(offsets.len().div_ceil(2), count);
+        assert_eq!(data, repaired);
+
+        let mut again = Vec::new();
+        assert_eq!(
+            0,
+            OpusMeta::repair_crcs(repaired.as_slice(), &mut again).unwrap()
+        );
+    }
+
+    #[test]
+    fn gapless_playback_info() {
+        // 20 ms CELT, 10 ms SILK, 2 frames of 2.5 ms CELT and 3 frames of 60 ms SILK
+        assert_eq!(Some(960), packet_samples(&[0xFC]));
+        assert_eq!(Some(480), packet_samples(&[0x00, 0xFF]));
+        assert_eq!(Some(240), packet_samples(&[0x81]));
+        assert_eq!(Some(8640), packet_samples(&[0x1B, 0x03]));
+        assert_eq!(None, packet_samples(&[0x03]));
+        assert_eq!(None, packet_samples(&[]));
+
+        let info = OpusMeta::gapless_info("./res/tag_test.opus").unwrap();
+        assert_eq!(312, info.pre_skip);
+        assert_eq!(
+            OpusMeta::sample_count(std::fs::File::open("./res/tag_test.opus").unwrap()).unwrap(),
+            info.total_samples
+        );
+        // less than the last packet, which is at most 120 ms long
+        assert!(info.end_trim < 5760, "{info:?}");
+    }
+
+    #[test]
+    fn iterate_comments_lazily() {
+        let tags = VorbisComment::new("v", vec![("ARTIST", "a"), ("TITLE", "t"), ("ALBUM", "b")]);
+        let mut payload = tags.to_bytes(TAGS_MAGIC_STR).unwrap();
+        // invalid UTF-8 in the last comment
+        let last = payload.len() - 1;
+        payload[last] = 0xFF;
+        let page = OggPage::from_payload(HeaderType::SIMPLE, 0, 0, 1, &payload).unwrap();
+        assert!(VorbisComment::from_bytes(&payload, TAGS_MAGIC_STR).is_err());
+
+        let title = VorbisComment::iter_from(&page)
+            .find(|it| it.as_ref().map_or(true, |it| it.key == "TITLE"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Comment::from(("TITLE", "t")), title);
+
+        let comments = VorbisComment::iter_from(&page).collect_vec();
+        assert_eq!(3, comments.len());
+        assert!(matches!(
+            comments[2],
+            Err(Error::MalformedComment { index: 2, .. })
+        ));
+
+        let page = OggPage::from_payload(HeaderType::SIMPLE, 0, 0, 1, b"OpusHead").unwrap();
+        let comments = VorbisComment::iter_from(&page).collect_vec();
+        assert!(matches!(
+            comments[..],
+            [Err(Error::UnexpectedPacket { .. })]
+        ));
+    }
+
+    #[test]
+    fn stamp_encoder() {
+        let mut tags = OpusMeta::read_from_file("./res/tag_test.opus")
+            .unwrap()
+            .tags;
+        assert_eq!(Some("Lavf60.3.100"), tags.encoder());
+
+        tags.set_encoder("opus_tag 1.0", false);
+        assert_eq!(Some("opus_tag 1.0"), tags.encoder());
+        assert_eq!(1, tags.count("ENCODER"));
+        assert_eq!("Lavf60.3.100", tags.vendor);
+
+        tags.set_encoder("opus_tag 1.1", true);
+        assert_eq!(Some("opus_tag 1.1"), tags.encoder());
+        assert_eq!("opus_tag 1.1", tags.vendor);
+        assert_eq!(Some("opus_tag 1.1".to_owned()), tags.encoder_info().encoder);
+    }
+
+    #[test]
+    fn hash_content() {
+        let tags = VorbisComment::new("a", vec![("TITLE", "t"), ("ARTIST", "x"), ("ARTIST", "y")]);
+        let reordered =
+            VorbisComment::new("b", vec![("artist", "y"), ("Title", "t"), ("ARTIST", "x")]);
+        assert_eq!(tags.content_hash(), reordered.content_hash());
+
+        let mut edited = tags.clone();
+        edited.comments_mut().next().unwrap().value.push('!');
+        assert_ne!(tags.content_hash(), edited.content_hash());
+        let shifted = VorbisComment::new("a", vec![("TITLE", ""), ("TITLEt", "")]);
+        assert_ne!(
+            shifted.content_hash(),
+            VorbisComment::new("a", vec![("TITLE", "t"), ("TITLE", "")]).content_hash()
+        );
+        assert_ne!(
+            tags.content_hash(),
+            VorbisComment::empty("a").content_hash()
+        );
+    }
+
+    #[test]
+    fn reorder_to_template() {
+        let mut tags = VorbisComment::new(
+            "",
+            vec![
+                ("GENRE", "g"),
+                ("artist", "a1"),
+                ("COMMENT", "c"),
+                ("TITLE", "t"),
+                ("ARTIST", "a2"),
+            ],
+        );
+        tags.reorder_to(&["TITLE", "ARTIST", "ALBUM"]);
+        assert_eq!(
+            vec!["TITLE=t", "artist=a1", "ARTIST=a2", "GENRE=g", "COMMENT=c"],
+            tags.to_lines()
+        );
+    }
+
+    #[test]
+    fn read_only_headers() {
+        /// panics when reading past `limit`
+        struct LimitedRead<'a> {
+            data: &'a [u8],
+            limit: usize,
+        }
+        impl Read for LimitedRead<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.data.read(buf)?;
+                self.limit = self
+                    .limit
+                    .checked_sub(n)
+                    .expect("read past the comment header");
+                Ok(n)
+            }
+        }
+
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let (meta, header_len) = OpusMeta::read_from_counting(data.as_slice()).unwrap();
+        let limited = || LimitedRead {
+            data: data.as_slice(),
+            limit: header_len as usize,
+        };
+        assert_eq!(meta, OpusMeta::read_headers_only(limited()).unwrap());
+        assert_eq!(meta, OpusMeta::read_from(limited()).unwrap());
+    }
+
+    #[test]
+    fn set_multiple_values() {
+        let mut tags = VorbisComment::new(
+            "",
+            vec![("ARTIST", "old"), ("TITLE", "t"), ("artist", "older")],
+        );
+        let previous = tags.set_all("ARTIST", ["a", "b", "c"]);
+        assert_eq!(vec!["old", "older"], previous);
+        assert_eq!(
+            vec!["TITLE=t", "ARTIST=a", "ARTIST=b", "ARTIST=c"],
+            tags.to_lines()
+        );
+        assert!(tags.set_all("GENRE", Vec::<String>::new()).is_empty());
+        assert!(tags.set_all("ARTIST", Vec::<String>::new()).len() == 3);
+        assert!(!tags.contains_key("ARTIST"));
+    }
+
+    #[test]
+    fn diff_tags() {
+        let original = OpusMeta::read_from_file("./res/tag_test.opus").unwrap();
+        assert!(original.tag_diff(&original).is_empty());
+
+        let mut retagged = original.clone();
+        retagged.set_pre_skip(0);
+        retagged.tags.set_single("TITLE", "new title".to_owned());
+        retagged.tags.remove_all("genre");
+        retagged.tags.append_all([("ARTIST", "a"), ("ARTIST", "b")]);
+        retagged.tags.reorder_to(&["year"]);
+        assert_eq!(
+            vec![
+                TagChange::Removed {
+                    key: "genre".to_owned(),
+                    value: "genre".to_owned()
+                },
+                TagChange::Changed {
+                    key: "title".to_owned(),
+                    old: "title".to_owned(),
+                    new: "new title".to_owned()
+                },
+                TagChange::Added {
+                    key: "ARTIST".to_owned(),
+                    value: "a".to_owned()
+                },
+                TagChange::Added {
+                    key: "ARTIST".to_owned(),
+                    value: "b".to_owned()
+                },
+            ],
+            original.tag_diff(&retagged)
+        );
+
+        let swapped = VorbisComment::new("", vec![("ARTIST", "b"), ("ARTIST", "a")]);
+        let mut meta = original.clone();
+        meta.tags = VorbisComment::new("", vec![("ARTIST", "a"), ("ARTIST", "b"), ("ARTIST", "c")]);
+        let other = OpusMeta {
+            tags: swapped,
+            ..original
+        };
+        assert_eq!(
+            vec![TagChange::Removed {
+                key: "ARTIST".to_owned(),
+                value: "c".to_owned()
+            }],
+            meta.tag_diff(&other)
+        );
+    }
+
+    #[test]
+    fn reject_vorbis() {
+        let mut id_header = b"\x01vorbis".to_vec();
+        id_header.resize(30, 0);
+        let mut buf = Vec::new();
+        OggPage::new(crate::ogg::HeaderType::BOS, 0, 1, 0, vec![id_header])
+            .unwrap()
+            .write_to(&mut buf)
+            .unwrap();
+
+        let err = OpusMeta::read_from(buf.as_slice()).unwrap_err();
+        assert!(
+            matches!(&err, Error::UnsupportedCodec(codec) if codec == "Vorbis"),
+            "expected UnsupportedCodec, got {err:?}"
+        );
+    }
+    #[test]
+    fn reject_non_ogg() {
+        let err = OpusMeta::read_from_file("./res/id3test.mp3").unwrap_err();
+        assert!(
+            matches!(err, Error::MalformedData(_)),
+            "expected MalformedData, got {err:?}"
+        );
+    }
+
+    #[test]
+    fn retag_stream_equals_update() {
+        let buf = std::fs::read("./res/tag_test.opus").unwrap();
+        let new_tags = VorbisComment::new(
+            "something new",
+            vec![("TITLE", "erstmal weniger daten"), ("ARTIST", "jemand")],
+        );
+
+        let mut expected = Vec::new();
+        new_tags
+            .update_opus_tags(buf.as_slice(), &mut expected, WriteOptions::default())
+            .unwrap();
+
+        let mut streamed = Vec::new();
+        std::io::copy(&mut new_tags.retag_stream(buf.as_slice()), &mut streamed).unwrap();
+
+        assert_eq!(expected, streamed);
+    }
+
+    #[test]
+    fn renumber_tail_streaming() {
+        let data = std::fs::read("./res/tag_test.opus").unwrap();
+        let mut header = Vec::new();
+        let mut pages = OggPage::iterate_read(data.as_slice());
+        pages
+            .next()
+            .unwrap()
+            .unwrap()
+            .write_to(&mut header)
+            .unwrap();
+        let tags_ogg = pages.next().unwrap().unwrap();
+        let serial = tags_ogg.bitstream_serial_number;
+        tags_ogg.write_to(&mut header).unwrap();
+        // an endless tail, which can only be processed page by page
+        let tail = (2..).map(move |seq| {
+            let mut buf = Vec::new();
+            OggPage::from_payload(
+                HeaderType::SIMPLE,
+                seq as u64 * 960,
+                serial,
+                seq,
+                &[0; 1000],
+            )
+            .unwrap()
+            .write_to(&mut buf)
+            .unwrap();
+            std::io::Cursor::new(buf)
+        });
+        let mut source = CountingRead::new(MultiChain::new(
+            std::iter::once(std::io::Cursor::new(header)).chain(tail),
+        ));
+
+        // two comment pages instead of one, so every following page needs to be renumbered
+        let new_tags = VorbisComment::new("vendor", vec![("TITLE", "x".repeat(70_000))]);
+        let mut output = Vec::new();
+        new_tags
+            .retag_stream(&mut source)
+            .take(1 << 20)
+            .read_to_end(&mut output)
+            .unwrap();
+
+        // at most the page currently being copied is read ahead
+        assert!(
+            source.count < output.len() as u64 + 1028,
+            "read {} bytes ahead",
+            source.count - output.len() as u64
+        );
+        let pages = OggPage::iterate_read(output.as_slice())
+            .map_while(Result::ok)
+            .collect_vec();
+        assert!(pages.len() > 100);
+        for (i, page) in pages.iter().enumerate() {
+            assert_eq!(i as u32, page.page_sequence_number);
+        }
+    }
+
+    #[test]
+    fn retag_stream_reports_error() {
+        let new_tags = VorbisComment::empty("something new");
+        let mut streamed = Vec::new();
+        let err =
+            std::io::copy(&mut new_tags.retag_stream(&b"no opus"[..]), &mut streamed).unwrap_err();
+        assert_eq!(std::io::ErrorKind::UnexpectedEof, err.kind());
+        assert!(streamed.is_empty(), "nothing should be written on error");
+    }
 }
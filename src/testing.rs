@@ -0,0 +1,46 @@
+//! helpers to create opus streams in memory, so parsing can be tested without external files
+
+use crate::opus_tagger::{OpusHead, OpusWriter, VorbisComment, HEAD_MAGIC_STR};
+
+/// the serial number of the stream written by [`build_minimal_opus`]
+pub const MINIMAL_SERIAL: u32 = 0x6F70_7573;
+
+/// synthesizes a valid opus stream without audio, that consists of a stereo ID header page and the comment header pages of `tags`.
+///
+/// the ID header has a pre-skip of 312 samples, a sample rate of 48 kHz, no output gain and channel mapping family 0
+///
+/// # Panics
+/// when `tags` is too long to be encoded
+pub fn build_minimal_opus(tags: &VorbisComment) -> Vec<u8> {
+    let mut head_packet = HEAD_MAGIC_STR.to_vec();
+    head_packet.extend([1, 2]); // version and channel count
+    head_packet.extend(312u16.to_le_bytes());
+    head_packet.extend(48_000u32.to_le_bytes());
+    head_packet.extend(0i16.to_le_bytes());
+    head_packet.push(0); // mapping family
+    let head = OpusHead::from_bytes(&head_packet).expect("the ID header is valid");
+
+    let mut buf = Vec::new();
+    OpusWriter::new(head, tags.clone(), MINIMAL_SERIAL)
+        .write(std::iter::empty(), &mut buf)
+        .expect("tags need to fit into the comment header");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ogg::OggPage, opus_tagger::OpusMeta};
+
+    #[test]
+    fn read_minimal_opus() {
+        let tags = VorbisComment::new("vendor", vec![("TITLE", "minimal"), ("ARTIST", "a")]);
+        let data = build_minimal_opus(&tags);
+
+        let meta = OpusMeta::read_from_bytes(&data).unwrap();
+        assert_eq!(tags, meta.tags);
+        assert!(OpusMeta::check_complete(data.as_slice()).unwrap());
+        OpusMeta::validate_structure(data.as_slice()).unwrap();
+        assert_eq!(2, OggPage::count_pages(data.as_slice()).unwrap());
+    }
+}